@@ -1,42 +1,91 @@
 // Copyright 2021-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MPL-2.0
 
-use serde::{de::DeserializeOwned, Deserialize, Serialize};
-use serde_repr::{Deserialize_repr, Serialize_repr};
+pub use enumflags2::BitFlags;
+
+use enumflags2::{bitflags, make_bitflags};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
-use zvariant::Type;
+use tokio::io::AsyncWriteExt;
+use zvariant::{OwnedValue, Type, Value};
+
+mod layers;
+mod migrate;
+
+pub use self::layers::{Key, Layer, Layered, Origin, DROP_IN_DIR, VENDOR_PATH};
+pub use self::migrate::{parse, Parsed, Versioned};
 
 pub const SYSTEM_CACHE: &str = "/var/cache/pop-system-updater/cache.ron";
 pub const SYSTEM_PATH: &str = "/etc/pop-system-updater/config.ron";
 pub const LOCAL_CACHE: &str = ".cache/pop-system-updater/cache.ron";
 pub const LOCAL_PATH: &str = ".config/pop-system-updater/config.ron";
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize, Type)]
+#[derive(Clone, Debug, Deserialize, Serialize, Type)]
 pub struct Cache {
+    /// The version of the format that the file was written in.
+    pub version: u32,
     pub last_update: u64,
 }
 
-#[derive(Clone, Debug, Default, Deserialize, Serialize, Type)]
+impl Default for Cache {
+    fn default() -> Self {
+        Self {
+            version: Self::VERSION,
+            last_update: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, Type)]
 pub struct LocalCache {
+    /// The version of the format that the file was written in.
+    pub version: u32,
     pub last_update: u64,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+impl Default for LocalCache {
+    fn default() -> Self {
+        Self {
+            version: Self::VERSION,
+            last_update: 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
 pub struct Config {
+    /// The version of the format that the file was written in.
+    pub version: u32,
+
     /// If it should automatically update when updates are available.
     pub auto_update: bool,
 
     /// When updates should be scheduled, if updates should be scheduled for.
     pub schedule: Option<Schedule>,
+
+    /// Restrictions on the settings that may be changed over DBus.
+    #[serde(default)]
+    pub policy: Policy,
+
+    /// The package managers that the system is updated with.
+    #[serde(default)]
+    pub backends: Backends,
 }
 
 impl Config {
     #[must_use]
-    pub const fn default_schedule() -> Schedule {
+    pub fn default_schedule() -> Schedule {
         Schedule {
-            interval: Interval::Weekdays,
-            hour: 22,
-            minute: 0,
+            days: WEEKDAYS,
+            times: vec![Time {
+                hour: 22,
+                minute: 0,
+            }],
+            window: 0,
         }
     }
 }
@@ -44,14 +93,19 @@ impl Config {
 impl Default for Config {
     fn default() -> Self {
         Self {
+            version: Self::VERSION,
             auto_update: false,
             schedule: Some(Config::default_schedule()),
+            policy: Policy::default(),
+            backends: Backends::default(),
         }
     }
 }
 
 #[derive(Clone, Debug, Deserialize, Serialize, Type)]
 pub struct Local {
+    /// The version of the format that the file was written in.
+    pub version: u32,
     pub enabled: bool,
     pub notification_frequency: Frequency,
 }
@@ -59,6 +113,7 @@ pub struct Local {
 impl Default for Local {
     fn default() -> Self {
         Self {
+            version: Self::VERSION,
             enabled: true,
             notification_frequency: Frequency::Weekly,
         }
@@ -73,15 +128,305 @@ pub enum Frequency {
     Monthly = 2,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize, Type)]
+/// Restrictions that an admin places on the settings which users may change.
+///
+/// The policies of each config layer are combined, and the strictest takes effect.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Type, Value, OwnedValue)]
+pub struct Policy {
+    /// Automatic updates may not be disabled.
+    #[serde(default)]
+    pub auto_update_required: bool,
+
+    /// The fewest days of the week that updates may be scheduled on.
+    #[serde(default)]
+    pub min_scheduled_days: u8,
+
+    /// Minutes that an update may be postponed by in total, or `UNLIMITED`.
+    ///
+    /// Updates may not be skipped while this is limited.
+    #[serde(default = "unlimited")]
+    pub max_deferral: u32,
+}
+
+impl Policy {
+    pub const UNLIMITED: u32 = u32::MAX;
+
+    /// Tightens this policy with the restrictions of another.
+    pub fn combine(&mut self, other: &Policy) {
+        self.auto_update_required |= other.auto_update_required;
+        self.min_scheduled_days = self.min_scheduled_days.max(other.min_scheduled_days);
+        self.max_deferral = self.max_deferral.min(other.max_deferral);
+    }
+
+    #[must_use]
+    pub fn deferral_limited(&self) -> bool {
+        self.max_deferral != Self::UNLIMITED
+    }
+
+    #[must_use]
+    pub fn is_unrestricted(&self) -> bool {
+        *self == Self::default()
+    }
+
+    /// Checks if a schedule occurs on as many days as this policy requires.
+    #[must_use]
+    pub fn permits(&self, schedule: &Schedule) -> bool {
+        !schedule.is_empty() && schedule.days.len() >= usize::from(self.min_scheduled_days)
+    }
+
+    /// Widens a schedule that this policy does not permit to every day of the
+    /// week, at the default time if it had none.
+    pub fn enforce(&self, schedule: &mut Schedule) {
+        if self.permits(schedule) {
+            return;
+        }
+
+        if schedule.times.is_empty() {
+            schedule.times = Config::default_schedule().times;
+        }
+
+        if schedule.days.len() < usize::from(self.min_scheduled_days.max(1)) {
+            schedule.days = BitFlags::all();
+        }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Self {
+            auto_update_required: false,
+            min_scheduled_days: 0,
+            max_deferral: Self::UNLIMITED,
+        }
+    }
+}
+
+fn unlimited() -> u32 {
+    Policy::UNLIMITED
+}
+
+/// A package manager that the system may be updated with.
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Type)]
+#[repr(u32)]
+pub enum Backend {
+    Apt = 0,
+    Flatpak = 1,
+    Fwupd = 2,
+    Nix = 3,
+    Snap = 4,
+}
+
+impl Backend {
+    pub const ALL: [Backend; 5] = [
+        Backend::Apt,
+        Backend::Flatpak,
+        Backend::Fwupd,
+        Backend::Nix,
+        Backend::Snap,
+    ];
+
+    /// The backend which is sent over DBus as the given code.
+    #[must_use]
+    pub fn from_code(code: u32) -> Option<Self> {
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|&backend| backend as u32 == code)
+    }
+}
+
+/// Which package managers the system is updated with, and how.
+///
+/// For example, to never update snaps, and to update firmware after apt:
+///
+/// ```ron
+/// backends: Some((enabled: [Apt, Fwupd, Flatpak, Nix], sequential: true))
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Type)]
+pub struct Backends {
+    /// The package managers that are updated with, in the order that they are started.
+    #[serde(default = "all_backends")]
+    pub enabled: Vec<Backend>,
+
+    /// Each package manager waits for the one before it to finish, instead of all updating at once.
+    #[serde(default)]
+    pub sequential: bool,
+
+    /// Only the system's flatpaks are updated, and not those of each user.
+    #[serde(default)]
+    pub flatpak_system_only: bool,
+
+    /// Which of apt's updates are installed.
+    #[serde(default)]
+    pub apt: AptPolicy,
+}
+
+impl Backends {
+    #[must_use]
+    pub fn is_enabled(&self, backend: Backend) -> bool {
+        self.enabled.contains(&backend)
+    }
+}
+
+impl Default for Backends {
+    fn default() -> Self {
+        Self {
+            enabled: all_backends(),
+            sequential: false,
+            flatpak_system_only: false,
+            apt: AptPolicy::default(),
+        }
+    }
+}
+
+fn all_backends() -> Vec<Backend> {
+    Backend::ALL.to_vec()
+}
+
+impl<'a> From<Backends> for Value<'a> {
+    fn from(backends: Backends) -> Self {
+        let enabled: Vec<u32> = backends
+            .enabled
+            .iter()
+            .map(|&backend| backend as u32)
+            .collect();
+
+        let apt = backends.apt;
+
+        Value::from((
+            enabled,
+            backends.sequential,
+            backends.flatpak_system_only,
+            (apt.security_only, apt.defer_days, apt.phased),
+        ))
+    }
+}
+
+impl TryFrom<OwnedValue> for Backends {
+    type Error = zvariant::Error;
+
+    fn try_from(value: OwnedValue) -> Result<Self, Self::Error> {
+        let (enabled, sequential, flatpak_system_only, (security_only, defer_days, phased)) =
+            <(Vec<u32>, bool, bool, (bool, u16, bool))>::try_from(value)?;
+
+        Ok(Backends {
+            enabled: enabled.into_iter().filter_map(Backend::from_code).collect(),
+            sequential,
+            flatpak_system_only,
+            apt: AptPolicy {
+                security_only,
+                defer_days,
+                phased,
+            },
+        })
+    }
+}
+
+/// Which of apt's updates are installed, and when. Updates which are held back
+/// are neither listed nor downloaded, until the policy allows them.
+///
+/// For example, to only install security updates, and to install every update
+/// as soon as it is released:
+///
+/// ```ron
+/// backends: Some((apt: (security_only: true, phased: false)))
+/// ```
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Type)]
+pub struct AptPolicy {
+    /// Only updates from `-security` pockets are installed.
+    #[serde(default)]
+    pub security_only: bool,
+
+    /// Days to hold back updates which are not for security, after the system first sees them.
+    #[serde(default)]
+    pub defer_days: u16,
+
+    /// Updates with a `Phased-Update-Percentage` are held back until the system is in their phase.
+    #[serde(default = "phased")]
+    pub phased: bool,
+}
+
+impl Default for AptPolicy {
+    fn default() -> Self {
+        Self {
+            security_only: false,
+            defer_days: 0,
+            phased: true,
+        }
+    }
+}
+
+fn phased() -> bool {
+    true
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Type)]
 pub struct Schedule {
-    pub interval: Interval,
+    /// Days of the week that updates are scheduled on.
+    pub days: BitFlags<Interval>,
+    /// Times of the day that updates are scheduled at.
+    pub times: Vec<Time>,
+    /// Minutes after the scheduled time that the update may randomly start within.
+    ///
+    /// An update which could not start inside of this window is skipped.
+    #[serde(default)]
+    pub window: u16,
+}
+
+impl Schedule {
+    /// A schedule without any days or times never occurs.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.days.is_empty() || self.times.is_empty()
+    }
+}
+
+impl<'a> From<Schedule> for Value<'a> {
+    fn from(schedule: Schedule) -> Self {
+        let times: Vec<(u8, u8)> = schedule
+            .times
+            .iter()
+            .map(|time| (time.hour, time.minute))
+            .collect();
+
+        Value::from((schedule.days.bits(), times, schedule.window))
+    }
+}
+
+impl TryFrom<OwnedValue> for Schedule {
+    type Error = zvariant::Error;
+
+    fn try_from(value: OwnedValue) -> Result<Self, Self::Error> {
+        let (days, times, window) = <(u8, Vec<(u8, u8)>, u16)>::try_from(value)?;
+
+        Ok(Schedule {
+            days: BitFlags::from_bits_truncate(days),
+            times: times
+                .into_iter()
+                .map(|(hour, minute)| Time { hour, minute })
+                .collect(),
+            window,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Type)]
+pub struct Time {
     pub hour: u8,
     pub minute: u8,
 }
 
+impl Time {
+    /// If the hour and minute are those of a time of day.
+    #[must_use]
+    pub fn is_valid(&self) -> bool {
+        self.hour < 24 && self.minute < 60
+    }
+}
+
+#[bitflags]
 #[repr(u8)]
-#[derive(Copy, Clone, Debug, Deserialize_repr, Serialize_repr, Type)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Type)]
 pub enum Interval {
     Monday = 1,
     Tuesday = 1 << 1,
@@ -90,77 +435,293 @@ pub enum Interval {
     Friday = 1 << 4,
     Saturday = 1 << 5,
     Sunday = 1 << 6,
-    Weekdays = 1 << 7,
 }
 
+pub const WEEKDAYS: BitFlags<Interval> =
+    make_bitflags!(Interval::{Monday | Tuesday | Wednesday | Thursday | Friday});
+
 pub async fn load_session() -> Local {
-    load(&session_path()).await
+    load(&session_path(), SESSION_ACCESS).await
 }
 
 pub async fn load_session_cache() -> LocalCache {
-    load(&session_cache_path()).await
+    load(&session_cache_path(), SESSION_ACCESS).await
 }
 
+/// The system config that is in effect, after merging each of its layers.
 pub async fn load_system() -> Config {
-    load(Path::new(SYSTEM_PATH)).await
+    load_system_layers().await.config
+}
+
+/// Reads each layer of the system config, and merges them.
+pub async fn load_system_layers() -> Layered {
+    let mut layers = Vec::new();
+
+    if let Some(layer) = read_layer(Path::new(VENDOR_PATH)).await {
+        layers.push((Origin::Vendor, layer));
+    }
+
+    // The admin's layer is only written once it has been changed, so that
+    // vendor defaults continue to apply until then.
+    if let Some(layer) = read(Path::new(SYSTEM_PATH), SYSTEM_ACCESS).await {
+        layers.push((Origin::System, layer));
+    }
+
+    for path in drop_ins().await {
+        if let Some(layer) = read_layer(&path).await {
+            layers.push((Origin::DropIn(path), layer));
+        }
+    }
+
+    Layered::new(layers)
 }
 
 pub async fn load_system_cache() -> Cache {
-    load(Path::new(SYSTEM_CACHE)).await
+    load(Path::new(SYSTEM_CACHE), SYSTEM_ACCESS).await
+}
+
+pub async fn write_session(config: &Local) -> io::Result<()> {
+    write(&session_path(), config, SESSION_ACCESS).await
+}
+
+pub async fn write_session_cache(cache: &LocalCache) -> io::Result<()> {
+    write(&session_cache_path(), cache, SESSION_ACCESS).await
+}
+
+pub async fn write_system(config: &Layer) -> io::Result<()> {
+    write(Path::new(SYSTEM_PATH), config, SYSTEM_ACCESS).await
+}
+
+pub async fn write_system_cache(cache: &Cache) -> io::Result<()> {
+    write(Path::new(SYSTEM_CACHE), cache, SYSTEM_ACCESS).await
+}
+
+/// Reads a file of the system service's state, which is owned by root.
+pub(crate) async fn read_system_file<T: Versioned>(path: &Path) -> Option<T> {
+    read(path, SYSTEM_ACCESS).await
 }
 
-pub async fn write_session(config: &Local) {
-    write(&session_path(), config).await;
+/// Atomically writes a file of the system service's state, which is owned by root.
+pub(crate) async fn write_system_file<T: Versioned>(path: &Path, value: &T) -> io::Result<()> {
+    write(path, value, SYSTEM_ACCESS).await
 }
 
-pub async fn write_session_cache(cache: &LocalCache) {
-    write(&session_cache_path(), cache).await;
+/// The permissions and ownership given to config files, and the directories containing them.
+#[derive(Clone, Copy)]
+struct Access {
+    dir_mode: u32,
+    file_mode: u32,
+    owner: Option<(libc::uid_t, libc::gid_t)>,
 }
 
-pub async fn write_system(config: &Config) {
-    write(Path::new(SYSTEM_PATH), config).await;
+/// System files are owned by root, and readable by the desktop.
+const SYSTEM_ACCESS: Access = Access {
+    dir_mode: 0o755,
+    file_mode: 0o644,
+    owner: Some((0, 0)),
+};
+
+/// Session files are owned by the user writing them, and private to them.
+const SESSION_ACCESS: Access = Access {
+    dir_mode: 0o700,
+    file_mode: 0o600,
+    owner: None,
+};
+
+async fn load<T: Versioned>(path: &Path, access: Access) -> T {
+    match read_file(path, access).await {
+        Ok(config) => return config,
+        // The file is kept for the release that wrote it, and defaults are used until then.
+        Err(Unread::Newer) => return T::default(),
+        Err(Unread::Missing | Unread::Invalid) => (),
+    }
+
+    let config = T::default();
+    if let Err(why) = write(path, &config, access).await {
+        error!("failed to write default config: {}", why);
+    }
+
+    config
+}
+
+/// Why a config was not read.
+enum Unread {
+    Missing,
+    Invalid,
+    Newer,
 }
 
-pub async fn write_system_cache(cache: &Cache) {
-    write(Path::new(SYSTEM_CACHE), cache).await;
+/// Reads a config if it exists, migrating it if it was written in an older format.
+async fn read<T: Versioned>(path: &Path, access: Access) -> Option<T> {
+    read_file(path, access).await.ok()
 }
 
-async fn load<T: Default + DeserializeOwned + Serialize>(path: &Path) -> T {
+async fn read_file<T: Versioned>(path: &Path, access: Access) -> Result<T, Unread> {
     info!("loading config: {:?}", path);
-    let file;
-    if let Ok(file_) = tokio::fs::read_to_string(path).await {
-        file = file_;
-        match ron::from_str::<T>(&file) {
-            Ok(config) => return config,
-            Err(why) => {
-                error!("failed to read config: {}", why);
+    let file = tokio::fs::read_to_string(path)
+        .await
+        .map_err(|_| Unread::Missing)?;
+
+    match migrate::parse::<T>(&file) {
+        Parsed::Current(config) => Ok(config),
+        Parsed::Migrated(config) => {
+            info!("migrated config to version {}: {:?}", T::VERSION, path);
+            if let Err(why) = write(path, &config, access).await {
+                error!("failed to write migrated config: {}", why);
             }
+
+            Ok(config)
+        }
+        Parsed::Newer(version) => {
+            error!(
+                "ignoring config {:?} written in version {}, which is newer than {}",
+                path,
+                version,
+                T::VERSION
+            );
+            Err(Unread::Newer)
+        }
+        Parsed::Invalid(why) => {
+            error!("failed to read config: {}", why);
+            backup(path).await;
+            Err(Unread::Invalid)
         }
     }
+}
 
-    let config = T::default();
-    write(path, &config).await;
-    config
+/// Reads a layer which is managed by the vendor or admin, and thus never written to.
+async fn read_layer(path: &Path) -> Option<Layer> {
+    let file = tokio::fs::read_to_string(path).await.ok()?;
+    info!("loading config layer: {:?}", path);
+
+    match migrate::parse::<Layer>(&file) {
+        Parsed::Current(layer) | Parsed::Migrated(layer) => Some(layer),
+        Parsed::Newer(version) => {
+            error!(
+                "ignoring config layer {:?} written in version {}, which is newer than {}",
+                path,
+                version,
+                Layer::VERSION
+            );
+            None
+        }
+        Parsed::Invalid(why) => {
+            error!("ignoring invalid config layer {:?}: {}", path, why);
+            None
+        }
+    }
 }
 
-async fn write<T: Serialize>(path: &Path, config: &T) {
+/// The `.ron` files in the drop-in directory, sorted by their file names.
+async fn drop_ins() -> Vec<PathBuf> {
+    let mut paths = Vec::new();
+
+    if let Ok(mut entries) = tokio::fs::read_dir(DROP_IN_DIR).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension() == Some("ron".as_ref()) {
+                paths.push(path);
+            }
+        }
+    }
+
+    paths.sort();
+    paths
+}
+
+/// Moves an unreadable config aside, so that it is not lost when the default is written.
+async fn backup(path: &Path) {
+    let now = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
+
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(format!(".{}.bak", now));
+
+    match tokio::fs::rename(path, &backup).await {
+        Ok(()) => info!("moved unreadable config to {:?}", backup),
+        Err(why) => error!("failed to back up config to {:?}: {}", backup, why),
+    }
+}
+
+/// Atomically replaces a config file, so that it is never left partially written.
+///
+/// The config is written to a temporary file beside it, synced to disk, and then
+/// renamed over the original.
+///
+/// A file written by a newer release is never replaced, so that it is not lost
+/// to a format which cannot represent it.
+async fn write<T: Versioned>(path: &Path, config: &T, access: Access) -> io::Result<()> {
     info!("writing config: {:?}", path);
 
-    if let Some(parent) = path.parent() {
-        let _res = tokio::fs::create_dir(parent).await;
+    if let Ok(existing) = tokio::fs::read_to_string(path).await {
+        if let Some(version) = migrate::version_of(&existing).filter(|&v| v > T::VERSION) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!(
+                    "{:?} was written in version {}, which is newer than {}",
+                    path,
+                    version,
+                    T::VERSION
+                ),
+            ));
+        }
     }
 
-    let config = match ron::to_string(config) {
-        Ok(config) => config,
-        Err(why) => {
-            error!("failed to serialize config: {}", why);
-            return;
+    let config = ron::to_string(config).map_err(|why| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("failed to serialize config: {}", why),
+        )
+    })?;
+
+    let parent = path.parent().unwrap_or_else(|| Path::new("/"));
+
+    tokio::fs::DirBuilder::new()
+        .recursive(true)
+        .mode(access.dir_mode)
+        .create(parent)
+        .await?;
+
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    let temporary = PathBuf::from(temporary);
+
+    let result = async {
+        let mut file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(access.file_mode)
+            .open(&temporary)
+            .await?;
+
+        // The mode given when opening is masked by the umask.
+        file.set_permissions(std::fs::Permissions::from_mode(access.file_mode))
+            .await?;
+
+        if let Some((uid, gid)) = access.owner {
+            // SAFETY: the file descriptor is owned by `file`, which outlives this call.
+            if unsafe { libc::fchown(file.as_raw_fd(), uid, gid) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
         }
-    };
 
-    if let Err(why) = tokio::fs::write(path, config.as_bytes()).await {
-        error!("failed to write config file: {}", why);
+        file.write_all(config.as_bytes()).await?;
+        file.sync_all().await?;
+
+        tokio::fs::rename(&temporary, path).await?;
+
+        // Persist the rename itself.
+        tokio::fs::File::open(parent).await?.sync_all().await
     }
+    .await;
+
+    if result.is_err() {
+        let _res = tokio::fs::remove_file(&temporary).await;
+    }
+
+    result.map_err(|why| io::Error::new(why.kind(), format!("{:?}: {}", path, why)))
 }
 
 fn session_path() -> PathBuf {
@@ -0,0 +1,413 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! The system config is assembled from layers, which are merged in order.
+//!
+//! 1. Vendor defaults shipped by the distribution in [`VENDOR_PATH`].
+//! 2. The admin's config in [`SYSTEM_PATH`], which is changed by the DBus setters.
+//! 3. Drop-in files in [`DROP_IN_DIR`], which are applied in order of their file names.
+//!
+//! A layer only sets the values that it contains, and a value set by a later
+//! layer takes precedence. Each layer may also lock keys, which the DBus
+//! setters will then refuse to change. A layer may also carry a [`Policy`], which
+//! restricts the values that they may change to. For example, a drop-in which
+//! enforces automatic updates at 3 AM on Sundays, and allows postponing them
+//! by at most a day:
+//!
+//! ```ron
+//! (
+//!     auto_update: Some(true),
+//!     schedule: Some(Some((days: 64, times: [(hour: 3, minute: 0)]))),
+//!     locked: [AutoUpdate, Schedule],
+//!     policy: (auto_update_required: true, max_deferral: 1440),
+//! )
+//! ```
+//!
+//! The [`Backends`] that are updated with are set as a whole, by the last layer which sets them.
+//!
+//! [`SYSTEM_PATH`]: super::SYSTEM_PATH
+
+use super::{Backends, Config, Policy, Schedule, Versioned};
+use enumflags2::{bitflags, BitFlags};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+pub const VENDOR_PATH: &str = "/usr/share/pop-system-updater/config.ron";
+pub const DROP_IN_DIR: &str = "/etc/pop-system-updater/config.d";
+
+/// The keys of the system config which layers may set.
+#[bitflags]
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub enum Key {
+    AutoUpdate = 1,
+    Schedule = 1 << 1,
+    Backends = 1 << 2,
+}
+
+impl Key {
+    pub const ALL: [Key; 3] = [Key::AutoUpdate, Key::Schedule, Key::Backends];
+}
+
+/// A config file which sets some of the values of the system config.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Layer {
+    /// The version of the format that the file was written in.
+    #[serde(default)]
+    pub version: u32,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub auto_update: Option<bool>,
+
+    /// `Some(None)` disables scheduling.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schedule: Option<Option<Schedule>>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backends: Option<Backends>,
+
+    /// Keys which may not be changed over DBus.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub locked: Vec<Key>,
+
+    #[serde(default, skip_serializing_if = "Policy::is_unrestricted")]
+    pub policy: Policy,
+}
+
+impl Default for Layer {
+    fn default() -> Self {
+        Self {
+            version: Self::VERSION,
+            auto_update: None,
+            schedule: None,
+            backends: None,
+            locked: Vec::new(),
+            policy: Policy::default(),
+        }
+    }
+}
+
+impl From<Config> for Layer {
+    fn from(config: Config) -> Self {
+        Self {
+            version: Self::VERSION,
+            auto_update: Some(config.auto_update),
+            schedule: Some(config.schedule),
+            // Configs written before backends could be chosen left them to the vendor.
+            backends: None,
+            locked: Vec::new(),
+            policy: config.policy,
+        }
+    }
+}
+
+/// The layer that a value of the system config was set by.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Origin {
+    Default,
+    Vendor,
+    System,
+    DropIn(PathBuf),
+}
+
+impl Origin {
+    /// The file that the layer was read from.
+    #[must_use]
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            Origin::Default => None,
+            Origin::Vendor => Some(Path::new(VENDOR_PATH)),
+            Origin::System => Some(Path::new(super::SYSTEM_PATH)),
+            Origin::DropIn(path) => Some(path),
+        }
+    }
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.path() {
+            Some(path) => write!(f, "{}", path.display()),
+            None => f.write_str("default"),
+        }
+    }
+}
+
+/// The system config, merged from each of its layers.
+#[derive(Clone, Debug)]
+pub struct Layered {
+    /// The config that is in effect.
+    pub config: Config,
+    layers: Vec<(Origin, Layer)>,
+    origins: [Origin; 3],
+    locked: BitFlags<Key>,
+}
+
+impl Layered {
+    /// Merges layers given in order of precedence, from lowest to highest.
+    ///
+    /// An empty admin's layer is inserted before the drop-ins if it was not given.
+    #[must_use]
+    pub fn new(mut layers: Vec<(Origin, Layer)>) -> Self {
+        if !layers.iter().any(|(origin, _)| *origin == Origin::System) {
+            let position = layers
+                .iter()
+                .position(|(origin, _)| matches!(origin, Origin::DropIn(_)))
+                .unwrap_or(layers.len());
+
+            layers.insert(position, (Origin::System, Layer::default()));
+        }
+
+        let mut layered = Self {
+            config: Config::default(),
+            layers,
+            origins: [Origin::Default, Origin::Default, Origin::Default],
+            locked: BitFlags::empty(),
+        };
+
+        layered.merge();
+        layered
+    }
+
+    /// Checks if a key was locked by any layer.
+    #[must_use]
+    pub fn is_locked(&self, key: Key) -> bool {
+        self.locked.contains(key)
+    }
+
+    /// The layer which set the value of a key.
+    #[must_use]
+    pub fn origin(&self, key: Key) -> &Origin {
+        &self.origins[index(key)]
+    }
+
+    /// The drop-in which sets a key over the admin's layer, if one does.
+    ///
+    /// Changes to the admin's layer would have no effect on such a key.
+    #[must_use]
+    pub fn overridden_by(&self, key: Key) -> Option<&Origin> {
+        match self.origin(key) {
+            origin @ Origin::DropIn(_) => Some(origin),
+            _ => None,
+        }
+    }
+
+    /// The admin's layer, which is written to the system config file.
+    #[must_use]
+    pub fn system(&self) -> &Layer {
+        &self.layers[self.system_position()].1
+    }
+
+    /// Changes the admin's layer, and merges the layers again.
+    ///
+    /// Values set by drop-ins continue to take precedence over the admin's layer,
+    /// so callers refuse to change keys which are [`Layered::overridden_by`] one.
+    pub fn update(&mut self, change: impl FnOnce(&mut Layer)) {
+        let position = self.system_position();
+        change(&mut self.layers[position].1);
+        self.merge();
+    }
+
+    fn system_position(&self) -> usize {
+        self.layers
+            .iter()
+            .position(|(origin, _)| *origin == Origin::System)
+            .expect("system layer is missing")
+    }
+
+    fn merge(&mut self) {
+        let mut config = Config::default();
+        let mut origins = [Origin::Default, Origin::Default, Origin::Default];
+        let mut locked = BitFlags::empty();
+
+        for (origin, layer) in &self.layers {
+            if let Some(auto_update) = layer.auto_update {
+                config.auto_update = auto_update;
+                origins[index(Key::AutoUpdate)] = origin.clone();
+            }
+
+            if let Some(ref schedule) = layer.schedule {
+                config.schedule = schedule.clone();
+                origins[index(Key::Schedule)] = origin.clone();
+            }
+
+            if let Some(ref backends) = layer.backends {
+                config.backends = backends.clone();
+                origins[index(Key::Backends)] = origin.clone();
+            }
+
+            locked.extend(layer.locked.iter().copied());
+            config.policy.combine(&layer.policy);
+        }
+
+        if config.policy.auto_update_required {
+            config.auto_update = true;
+        }
+
+        // A schedule may have been set beneath a layer whose policy it does not meet.
+        if let Some(ref mut schedule) = config.schedule {
+            config.policy.enforce(schedule);
+        }
+
+        self.config = config;
+        self.origins = origins;
+        self.locked = locked;
+    }
+}
+
+fn index(key: Key) -> usize {
+    match key {
+        Key::AutoUpdate => 0,
+        Key::Schedule => 1,
+        Key::Backends => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Backend, BitFlags, Time};
+
+    fn layer(file: &str) -> Layer {
+        ron::from_str(file).expect("invalid layer")
+    }
+
+    #[test]
+    fn later_layers_take_precedence() {
+        let drop_in = Origin::DropIn(PathBuf::from("/etc/pop-system-updater/config.d/10.ron"));
+
+        let layered = Layered::new(vec![
+            (
+                Origin::Vendor,
+                layer("(auto_update: Some(true), schedule: Some(None))"),
+            ),
+            (Origin::System, layer("(auto_update: Some(false))")),
+            (
+                drop_in.clone(),
+                layer("(schedule: Some(Some((days: 64, times: [(hour: 3, minute: 0)]))))"),
+            ),
+        ]);
+
+        assert!(!layered.config.auto_update);
+        assert_eq!(layered.origin(Key::AutoUpdate), &Origin::System);
+
+        let schedule = layered
+            .config
+            .schedule
+            .as_ref()
+            .expect("schedule was not set");
+        assert_eq!(schedule.days.bits(), 64);
+        assert_eq!(layered.origin(Key::Schedule), &drop_in);
+    }
+
+    #[test]
+    fn unset_values_are_defaults() {
+        let layered = Layered::new(vec![(Origin::System, Layer::default())]);
+
+        assert_eq!(layered.config.auto_update, Config::default().auto_update);
+        assert_eq!(layered.origin(Key::AutoUpdate), &Origin::Default);
+        assert_eq!(layered.origin(Key::Schedule), &Origin::Default);
+    }
+
+    #[test]
+    fn policies_are_combined() {
+        let layered = Layered::new(vec![
+            (
+                Origin::Vendor,
+                layer("(policy: (max_deferral: 60, min_scheduled_days: 5))"),
+            ),
+            (Origin::System, layer("(auto_update: Some(false))")),
+            (
+                Origin::DropIn(PathBuf::from("/etc/pop-system-updater/config.d/10.ron")),
+                layer("(policy: (auto_update_required: true, max_deferral: 120))"),
+            ),
+        ]);
+
+        let policy = &layered.config.policy;
+        assert!(policy.auto_update_required);
+        assert_eq!(policy.min_scheduled_days, 5);
+        assert_eq!(policy.max_deferral, 60);
+        assert!(layered.config.auto_update);
+    }
+
+    #[test]
+    fn schedules_are_widened_to_the_policy() {
+        let layered = Layered::new(vec![
+            (
+                Origin::System,
+                layer("(schedule: Some(Some((days: 3, times: [(hour: 3, minute: 0)]))))"),
+            ),
+            (
+                Origin::DropIn(PathBuf::from("/etc/pop-system-updater/config.d/10.ron")),
+                layer("(policy: (min_scheduled_days: 5))"),
+            ),
+        ]);
+
+        let schedule = layered.config.schedule.as_ref().unwrap();
+        assert_eq!(schedule.days, BitFlags::all());
+        assert_eq!(schedule.times, [Time { hour: 3, minute: 0 }]);
+
+        let layered = Layered::new(vec![
+            (Origin::Vendor, layer("(policy: (min_scheduled_days: 2))")),
+            (
+                Origin::System,
+                layer("(schedule: Some(Some((days: 3, times: []))))"),
+            ),
+        ]);
+
+        let schedule = layered.config.schedule.as_ref().unwrap();
+        assert_eq!(schedule.days.bits(), 3);
+        assert_eq!(schedule.times, Config::default_schedule().times);
+    }
+
+    #[test]
+    fn locks_are_combined() {
+        let layered = Layered::new(vec![
+            (Origin::Vendor, layer("(locked: [Schedule])")),
+            (Origin::System, Layer::default()),
+        ]);
+
+        assert!(layered.is_locked(Key::Schedule));
+        assert!(!layered.is_locked(Key::AutoUpdate));
+    }
+
+    #[test]
+    fn backends_are_set_as_a_whole() {
+        let drop_in = Origin::DropIn(PathBuf::from("/etc/pop-system-updater/config.d/10.ron"));
+
+        let layered = Layered::new(vec![
+            (
+                Origin::System,
+                layer("(backends: Some((enabled: [Apt], flatpak_system_only: true)))"),
+            ),
+            (
+                drop_in.clone(),
+                layer("(backends: Some((enabled: [Apt, Fwupd], sequential: true)))"),
+            ),
+        ]);
+
+        let backends = &layered.config.backends;
+        assert_eq!(backends.enabled, [Backend::Apt, Backend::Fwupd]);
+        assert!(backends.sequential);
+        assert!(!backends.flatpak_system_only);
+        assert_eq!(layered.origin(Key::Backends), &drop_in);
+
+        let layered = Layered::new(vec![(Origin::System, Layer::default())]);
+        assert_eq!(layered.config.backends, Backends::default());
+    }
+
+    #[test]
+    fn drop_ins_override_updates() {
+        let drop_in = Origin::DropIn(PathBuf::from("/etc/pop-system-updater/config.d/10.ron"));
+        let mut layered = Layered::new(vec![(drop_in.clone(), layer("(auto_update: Some(true))"))]);
+
+        assert_eq!(layered.overridden_by(Key::AutoUpdate), Some(&drop_in));
+        assert_eq!(layered.overridden_by(Key::Schedule), None);
+
+        layered.update(|layer| layer.schedule = Some(None));
+
+        assert!(layered.config.schedule.is_none());
+        assert_eq!(layered.origin(Key::Schedule), &Origin::System);
+        assert_eq!(layered.overridden_by(Key::Schedule), None);
+    }
+}
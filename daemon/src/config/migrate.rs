@@ -0,0 +1,437 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! Upgrades config files written by older releases to the current format.
+//!
+//! Files written before versioning was introduced have no `version` field, and
+//! are recognized by their shape instead. Each historic format converts into the
+//! one that followed it, so that a file of any age takes the same path forward.
+
+use super::{
+    Backends, BitFlags, Cache, Config, Layer, Local, LocalCache, Policy, Schedule, Time, WEEKDAYS,
+};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+/// A config file whose format is versioned.
+pub trait Versioned: Default + DeserializeOwned + Serialize {
+    /// The version of the format written by this release.
+    const VERSION: u32;
+
+    /// Reads a file written in an older `version` of the format, or in one
+    /// which was not yet versioned if it is `None`.
+    fn migrate(version: Option<u32>, file: &str) -> Option<Self>;
+}
+
+/// A config file, as interpreted by this release.
+pub enum Parsed<T> {
+    /// Written in the current format.
+    Current(T),
+    /// Written in an older format, and converted to the current one.
+    Migrated(T),
+    /// Written by a newer release, in a format that this release does not know.
+    Newer(u32),
+    /// Not a file of this kind, with the reason why.
+    Invalid(String),
+}
+
+/// The field which every versioned format shares.
+#[derive(Deserialize)]
+struct Header {
+    #[serde(default, deserialize_with = "versioned")]
+    version: Option<u32>,
+}
+
+fn versioned<'de, D: serde::Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    u32::deserialize(deserializer).map(Some)
+}
+
+/// Reads a file in the format of the version that it declares.
+pub fn parse<T: Versioned>(file: &str) -> Parsed<T> {
+    let version = match ron::from_str::<Header>(file) {
+        Ok(header) => header.version,
+        Err(why) => return Parsed::Invalid(why.to_string()),
+    };
+
+    match version {
+        Some(version) if version > T::VERSION => Parsed::Newer(version),
+        Some(version) if version == T::VERSION => match ron::from_str(file) {
+            Ok(config) => Parsed::Current(config),
+            Err(why) => Parsed::Invalid(why.to_string()),
+        },
+        version => match T::migrate(version, file) {
+            Some(config) => Parsed::Migrated(config),
+            None => Parsed::Invalid(match version {
+                Some(version) => format!("not a valid file of version {}", version),
+                None => "not a valid file of any unversioned format".to_owned(),
+            }),
+        },
+    }
+}
+
+/// The version of the format of an existing file, if it declares one.
+#[must_use]
+pub fn version_of(file: &str) -> Option<u32> {
+    ron::from_str::<Header>(file).ok()?.version
+}
+
+impl Versioned for Config {
+    /// 0. A single day or weekdays, at a single time.
+    /// 1. Multiple days and multiple times.
+    /// 2. Versioned.
+    const VERSION: u32 = 2;
+
+    fn migrate(version: Option<u32>, file: &str) -> Option<Self> {
+        if version.is_some() {
+            return None;
+        }
+
+        ron::from_str::<v1::Config>(file)
+            .or_else(|_| ron::from_str::<v0::Config>(file).map(v1::Config::from))
+            .ok()
+            .map(Config::from)
+    }
+}
+
+impl Versioned for Layer {
+    /// 0..=2. The system config, which set every value.
+    /// 3. Layered, with values that may be unset, and locked keys.
+    const VERSION: u32 = 3;
+
+    fn migrate(version: Option<u32>, file: &str) -> Option<Self> {
+        match version {
+            // Layers written by an admin may leave out the version, as they may every other key.
+            None => ron::from_str::<Layer>(file)
+                .map(|layer| Layer {
+                    version: Layer::VERSION,
+                    ..layer
+                })
+                .ok()
+                .or_else(|| Config::migrate(None, file).map(Layer::from)),
+            Some(Config::VERSION) => ron::from_str::<Config>(file).ok().map(Layer::from),
+            Some(_) => None,
+        }
+    }
+}
+
+impl Versioned for Local {
+    /// 0. Unversioned.
+    /// 1. Versioned.
+    const VERSION: u32 = 1;
+
+    fn migrate(version: Option<u32>, file: &str) -> Option<Self> {
+        if version.is_some() {
+            return None;
+        }
+
+        ron::from_str::<v0::Local>(file).ok().map(Local::from)
+    }
+}
+
+impl Versioned for Cache {
+    /// 0. Unversioned.
+    /// 1. Versioned.
+    const VERSION: u32 = 1;
+
+    fn migrate(version: Option<u32>, file: &str) -> Option<Self> {
+        if version.is_some() {
+            return None;
+        }
+
+        ron::from_str::<v0::Cache>(file).ok().map(|cache| Cache {
+            version: Cache::VERSION,
+            last_update: cache.last_update,
+        })
+    }
+}
+
+impl Versioned for LocalCache {
+    /// 0. Unversioned.
+    /// 1. Versioned.
+    const VERSION: u32 = 1;
+
+    fn migrate(version: Option<u32>, file: &str) -> Option<Self> {
+        if version.is_some() {
+            return None;
+        }
+
+        ron::from_str::<v0::Cache>(file)
+            .ok()
+            .map(|cache| LocalCache {
+                version: LocalCache::VERSION,
+                last_update: cache.last_update,
+            })
+    }
+}
+
+/// The formats of the first release.
+mod v0 {
+    use super::Deserialize;
+    use crate::config::Frequency;
+
+    /// Schedules for weekdays were a variant alongside the days of the week.
+    pub const WEEKDAYS: u8 = 1 << 7;
+
+    #[derive(Deserialize)]
+    pub struct Config {
+        pub auto_update: bool,
+        pub schedule: Option<Schedule>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Schedule {
+        pub interval: u8,
+        pub hour: u8,
+        pub minute: u8,
+        #[serde(default)]
+        pub window: u16,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Local {
+        pub enabled: bool,
+        pub notification_frequency: Frequency,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Cache {
+        pub last_update: u64,
+    }
+}
+
+/// The system config before it was versioned.
+mod v1 {
+    use super::{v0, BitFlags, Deserialize, Schedule, Time, WEEKDAYS};
+
+    #[derive(Deserialize)]
+    pub struct Config {
+        pub auto_update: bool,
+        pub schedule: Option<Schedule>,
+    }
+
+    impl From<v0::Config> for Config {
+        fn from(config: v0::Config) -> Self {
+            Self {
+                auto_update: config.auto_update,
+                schedule: config.schedule.map(|schedule| Schedule {
+                    days: if schedule.interval == v0::WEEKDAYS {
+                        WEEKDAYS
+                    } else {
+                        BitFlags::from_bits_truncate(schedule.interval)
+                    },
+                    times: vec![Time {
+                        hour: schedule.hour,
+                        minute: schedule.minute,
+                    }],
+                    window: schedule.window,
+                }),
+            }
+        }
+    }
+}
+
+impl From<v1::Config> for Config {
+    fn from(config: v1::Config) -> Self {
+        Self {
+            version: Config::VERSION,
+            auto_update: config.auto_update,
+            schedule: config.schedule,
+            policy: Policy::default(),
+            backends: Backends::default(),
+        }
+    }
+}
+
+impl From<v0::Local> for Local {
+    fn from(config: v0::Local) -> Self {
+        Self {
+            version: Local::VERSION,
+            enabled: config.enabled,
+            notification_frequency: config.notification_frequency,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Frequency, Interval};
+
+    fn load<T: Versioned>(file: &str) -> T {
+        match parse::<T>(file) {
+            Parsed::Current(config) | Parsed::Migrated(config) => config,
+            Parsed::Newer(version) => panic!("file is of newer version {}", version),
+            Parsed::Invalid(why) => panic!("file was not migrated: {}", why),
+        }
+    }
+
+    #[test]
+    fn config_v0_single_day() {
+        let config: Config =
+            load("(auto_update:true,schedule:Some((interval:4,hour:3,minute:30)))");
+        let schedule = config.schedule.unwrap();
+
+        assert_eq!(config.version, Config::VERSION);
+        assert!(config.auto_update);
+        assert_eq!(schedule.days, Interval::Wednesday);
+        assert_eq!(
+            schedule.times,
+            vec![Time {
+                hour: 3,
+                minute: 30
+            }]
+        );
+        assert_eq!(schedule.window, 0);
+    }
+
+    #[test]
+    fn config_v0_weekdays() {
+        let config: Config =
+            load("(auto_update:false,schedule:Some((interval:128,hour:22,minute:0,window:60)))");
+        let schedule = config.schedule.unwrap();
+
+        assert!(!config.auto_update);
+        assert_eq!(schedule.days, WEEKDAYS);
+        assert_eq!(
+            schedule.times,
+            vec![Time {
+                hour: 22,
+                minute: 0
+            }]
+        );
+        assert_eq!(schedule.window, 60);
+    }
+
+    #[test]
+    fn config_v0_when_available() {
+        let config: Config = load("(auto_update:true,schedule:None)");
+        assert!(config.schedule.is_none());
+    }
+
+    #[test]
+    fn config_v1() {
+        let config: Config = load(
+            "(auto_update:true,schedule:Some((days:96,times:[(hour:6,minute:0),(hour:18,minute:15)],window:0)))",
+        );
+        let schedule = config.schedule.unwrap();
+
+        assert_eq!(config.version, Config::VERSION);
+        assert_eq!(schedule.days, Interval::Saturday | Interval::Sunday);
+        assert_eq!(
+            schedule.times,
+            vec![
+                Time { hour: 6, minute: 0 },
+                Time {
+                    hour: 18,
+                    minute: 15
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn config_current() {
+        let written = ron::to_string(&Config::default()).unwrap();
+        let config: Config = ron::from_str(&written).unwrap();
+        assert_eq!(config.version, Config::VERSION);
+    }
+
+    #[test]
+    fn config_invalid() {
+        assert!(matches!(
+            parse::<Config>("(auto_update:maybe)"),
+            Parsed::Invalid(_)
+        ));
+        assert!(matches!(parse::<Config>(""), Parsed::Invalid(_)));
+    }
+
+    #[test]
+    fn migrations_are_chosen_by_version() {
+        // A versioned file is not mistaken for an unversioned one of the same shape.
+        assert!(matches!(
+            parse::<Config>("(version:1,auto_update:true,schedule:None)"),
+            Parsed::Invalid(_)
+        ));
+
+        assert!(matches!(
+            parse::<Layer>("(version:2,auto_update:true,schedule:None)"),
+            Parsed::Migrated(_)
+        ));
+        assert!(matches!(
+            parse::<Layer>("(version:3,auto_update:Some(true))"),
+            Parsed::Current(_)
+        ));
+    }
+
+    #[test]
+    fn newer_versions_are_not_migrated() {
+        assert!(matches!(
+            parse::<Config>("(version:3,auto_update:true,schedule:None)"),
+            Parsed::Newer(3)
+        ));
+        assert!(matches!(
+            parse::<Layer>("(version:4,auto_update:Some(true),unknown:[])"),
+            Parsed::Newer(4)
+        ));
+        assert_eq!(version_of("(version:4,unknown:[])"), Some(4));
+        assert_eq!(version_of("(last_update:1666000000)"), None);
+    }
+
+    #[test]
+    fn layer_without_version() {
+        let layer: Layer = load("(auto_update:Some(false))");
+
+        assert_eq!(layer.version, Layer::VERSION);
+        assert_eq!(layer.auto_update, Some(false));
+    }
+
+    #[test]
+    fn layer_from_config() {
+        let layer: Layer = load("(version:2,auto_update:true,schedule:None)");
+
+        assert_eq!(layer.version, Layer::VERSION);
+        assert_eq!(layer.auto_update, Some(true));
+        assert!(matches!(layer.schedule, Some(None)));
+
+        let layer: Layer =
+            load("(auto_update:false,schedule:Some((interval:128,hour:22,minute:0)))");
+        let schedule = layer.schedule.flatten().unwrap();
+
+        assert_eq!(layer.auto_update, Some(false));
+        assert_eq!(schedule.days, WEEKDAYS);
+    }
+
+    #[test]
+    fn layer_current() {
+        let written = ron::to_string(&Layer {
+            auto_update: Some(true),
+            ..Layer::default()
+        })
+        .unwrap();
+        let layer: Layer = ron::from_str(&written).unwrap();
+
+        assert_eq!(layer.version, Layer::VERSION);
+        assert_eq!(layer.auto_update, Some(true));
+        assert!(layer.schedule.is_none());
+    }
+
+    #[test]
+    fn local_v0() {
+        let config: Local = load("(enabled:false,notification_frequency:Monthly)");
+
+        assert_eq!(config.version, Local::VERSION);
+        assert!(!config.enabled);
+        assert!(matches!(config.notification_frequency, Frequency::Monthly));
+    }
+
+    #[test]
+    fn caches_v0() {
+        let cache: Cache = load("(last_update:1666000000)");
+        assert_eq!(cache.version, Cache::VERSION);
+        assert_eq!(cache.last_update, 1_666_000_000);
+
+        let cache: LocalCache = load("(last_update:1666000000)");
+        assert_eq!(cache.version, LocalCache::VERSION);
+        assert_eq!(cache.last_update, 1_666_000_000);
+    }
+}
@@ -1,7 +1,9 @@
 // Copyright 2021-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::config::Schedule;
+use crate::config::{Backends, Policy, Schedule};
+use crate::history::Report;
+use crate::updates::Available;
 
 #[dbus_proxy(
     interface = "com.system76.SystemUpdater",
@@ -11,21 +13,75 @@ use crate::config::Schedule;
 pub trait Client {
     fn auto_update_set(&mut self, enable: bool) -> zbus::Result<()>;
 
+    fn backends_set(&mut self, backends: Backends) -> zbus::Result<()>;
+
     fn check_for_updates(&mut self) -> zbus::Result<()>;
 
+    fn config_layers(&mut self) -> zbus::Result<Vec<(String, String, bool)>>;
+
     fn is_updating(&mut self) -> zbus::Result<bool>;
 
+    fn list_updates(&mut self) -> zbus::Result<Vec<Available>>;
+
     fn repair(&mut self) -> zbus::Result<()>;
 
+    fn update_history(&mut self, offset: u32, limit: u32) -> zbus::Result<Vec<Report>>;
+
+    fn update_log(&mut self, started: u64) -> zbus::Result<String>;
+
+    fn update_postpone(&mut self, minutes: u32) -> zbus::Result<()>;
+
     fn update_scheduling_disable(&mut self) -> zbus::Result<()>;
 
     fn update_scheduling_set(&mut self, schedule: Schedule) -> zbus::Result<()>;
 
+    fn update_skip_next(&mut self) -> zbus::Result<()>;
+
     fn update_system(&mut self) -> zbus::Result<()>;
 
+    #[dbus_proxy(property)]
+    fn auto_update(&self) -> zbus::Result<bool>;
+
+    #[dbus_proxy(property)]
+    fn backends(&self) -> zbus::Result<Backends>;
+
+    #[dbus_proxy(property, name = "UpdatesAvailable")]
+    fn has_updates(&self) -> zbus::Result<bool>;
+
+    #[dbus_proxy(property)]
+    fn last_check(&self) -> zbus::Result<u64>;
+
+    #[dbus_proxy(property)]
+    fn last_update(&self) -> zbus::Result<u64>;
+
+    #[dbus_proxy(property)]
+    fn next_scheduled_update(&self) -> zbus::Result<u64>;
+
+    #[dbus_proxy(property)]
+    fn policy(&self) -> zbus::Result<Policy>;
+
+    #[dbus_proxy(property)]
+    fn schedule(&self) -> zbus::Result<Schedule>;
+
+    #[dbus_proxy(property)]
+    fn updating(&self) -> zbus::Result<bool>;
+
+    #[dbus_proxy(signal)]
+    fn error(
+        &self,
+        source: &str,
+        code: u32,
+        command: &str,
+        why: &str,
+        output: &str,
+    ) -> zbus::Result<()>;
+
     #[dbus_proxy(signal)]
-    fn error(&self, why: &str) -> zbus::Result<()>;
+    fn log(&self, source: &str, stream: &str, line: &str) -> zbus::Result<()>;
 
     #[dbus_proxy(signal)]
     fn progress(&self, source: &str, percent: u8) -> zbus::Result<()>;
+
+    #[dbus_proxy(signal)]
+    fn update_finished(&self, report: Report) -> zbus::Result<()>;
 }
@@ -14,10 +14,12 @@ pub struct LocalServer {
 #[dbus_interface(name = "com.system76.SystemUpdater.Local")]
 impl LocalServer {
     /// Enable or disable notifications
-    async fn notifications_enabled(&mut self, enabled: bool) {
+    async fn notifications_enabled(&mut self, enabled: bool) -> zbus::fdo::Result<()> {
         self.config.enabled = enabled;
-        crate::config::write_session(&self.config).await;
+        write_config(&self.config).await?;
         let _res = self.service.send(LocalEvent::UpdateConfig(self.config.clone())).await;
+
+        Ok(())
     }
 
     /// Get the frequency that the notification prompt will show.
@@ -32,9 +34,15 @@ impl LocalServer {
     ) -> zbus::fdo::Result<()> {
         self.config.notification_frequency = frequency;
 
-        crate::config::write_session(&self.config).await;
+        write_config(&self.config).await?;
         let _res = self.service.send(LocalEvent::UpdateConfig(self.config.clone())).await;
 
         Ok(())
     }
 }
+
+async fn write_config(config: &config::Local) -> zbus::fdo::Result<()> {
+    crate::config::write_session(config).await.map_err(|why| {
+        zbus::fdo::Error::IOError(format!("failed to write session config: {}", why))
+    })
+}
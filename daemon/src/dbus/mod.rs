@@ -4,9 +4,11 @@
 pub mod client;
 pub mod local_client;
 pub mod local_server;
+pub mod rate_limit;
 pub mod server;
 
-use crate::config::{Frequency, Local, Schedule};
+use crate::config::{Backends, Frequency, Local, Schedule};
+use crate::updates::Available;
 
 // Where this service's interface is being served at.
 pub const IFACE: &str = "/com/system76/SystemUpdater";
@@ -15,18 +17,32 @@ pub const IFACE_LOCAL: &str = "/com/system76/SystemUpdater/Local";
 #[derive(Debug)]
 pub enum Event {
     CheckForUpdates,
+    /// Each key of the system config, the layer that set it, and if it is locked.
+    ConfigLayers(flume::Sender<zbus::fdo::Result<Vec<(String, String, bool)>>>),
     Exit,
+    ListUpdates(flume::Sender<zbus::fdo::Result<Vec<Available>>>),
+    Postpone(u32, Reply),
+    PrefetchComplete,
+    Reload,
     Repair,
+    Resume,
     ScheduleWhenAvailable,
-    SetSchedule(Option<Schedule>),
-    SetAutoUpdate(bool),
+    SetBackends(Backends, Reply),
+    SetSchedule(Option<Schedule>, Reply),
+    SetAutoUpdate(bool, Reply),
+    SkipNext(Reply),
     Update,
-    UpdateComplete,
+    /// An update finished, and whether every source succeeded.
+    UpdateComplete(bool),
 }
 
+/// Reports whether a request succeeded back to the DBus caller.
+pub type Reply = flume::Sender<zbus::fdo::Result<()>>;
+
 #[derive(Debug)]
 pub enum LocalEvent {
     CheckUpdates,
+    SystemConfigChanged(bool),
     UpdateConfig(Local),
 }
 
@@ -35,11 +51,25 @@ pub struct PopService<E> {
 }
 
 impl<E: std::fmt::Debug> PopService<E> {
-    async fn send(&mut self, event: E) -> zbus::fdo::Result<()> {
+    async fn send(&self, event: E) -> zbus::fdo::Result<()> {
         if let Err(why) = self.sender.send_async(event).await {
             Err(zbus::fdo::Error::Failed(format!("{}", why)))
         } else {
             Ok(())
         }
     }
+
+    /// Sends an event, and waits for the service to reply with its outcome.
+    async fn request<T>(
+        &self,
+        event: impl FnOnce(flume::Sender<zbus::fdo::Result<T>>) -> E,
+    ) -> zbus::fdo::Result<T> {
+        let (reply, response) = flume::bounded(1);
+        self.send(event(reply)).await?;
+
+        response
+            .recv_async()
+            .await
+            .map_err(|why| zbus::fdo::Error::Failed(format!("{}", why)))?
+    }
 }
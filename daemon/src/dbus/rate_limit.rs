@@ -0,0 +1,86 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! Limits how often each caller may make queries which do not require authorization.
+//!
+//! Such queries may be made by any local user, and some of them are expensive to
+//! answer, such as listing updates, which runs every package manager.
+
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Queries that each caller may make within a [`RateLimit::WINDOW`].
+pub const MAX_QUERIES: u32 = 30;
+
+pub struct RateLimit {
+    /// When each caller's window started, and the queries they made within it.
+    callers: Mutex<HashMap<String, (Instant, u32)>>,
+}
+
+impl RateLimit {
+    pub const WINDOW: Duration = Duration::from_secs(60);
+
+    #[must_use]
+    pub fn new() -> Self {
+        Self {
+            callers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Counts a query by a caller, and checks if it is within their limit.
+    ///
+    /// # Errors
+    ///
+    /// Returns `LimitsExceeded` if the caller made too many queries recently.
+    pub fn check(&self, caller: &str, now: Instant) -> zbus::fdo::Result<()> {
+        let mut callers = self.callers.lock().unwrap_or_else(|e| e.into_inner());
+
+        // Forget callers whose windows have passed, so that the map does not grow.
+        callers.retain(|_, (started, _)| now.duration_since(*started) < Self::WINDOW);
+
+        let (_, queries) = callers.entry(caller.to_owned()).or_insert((now, 0));
+
+        if *queries >= MAX_QUERIES {
+            info!("refusing query from {}: too many queries", caller);
+            return Err(zbus::fdo::Error::LimitsExceeded(format!(
+                "no more than {} queries may be made per minute",
+                MAX_QUERIES
+            )));
+        }
+
+        *queries += 1;
+        Ok(())
+    }
+}
+
+impl Default for RateLimit {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn callers_are_limited_within_a_window() {
+        let limit = RateLimit::new();
+        let start = Instant::now();
+
+        for _ in 0..MAX_QUERIES {
+            assert!(limit.check(":1.42", start).is_ok());
+        }
+
+        assert!(matches!(
+            limit.check(":1.42", start),
+            Err(zbus::fdo::Error::LimitsExceeded(_))
+        ));
+
+        // Other callers have their own limits.
+        assert!(limit.check(":1.43", start).is_ok());
+
+        assert!(limit.check(":1.42", start + RateLimit::WINDOW).is_ok());
+    }
+}
@@ -1,51 +1,258 @@
 // Copyright 2021-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MPL-2.0
 
+use super::rate_limit::RateLimit;
 use super::{Event, PopService};
-use crate::config::Schedule;
+use crate::config::{Backends, BitFlags, Config, Policy, Schedule};
+use crate::history::{self, Report};
+use crate::polkit::{Action, Authority};
+use crate::updates::Available;
 use std::future::Future;
 use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex, MutexGuard, PoisonError,
 };
-use zbus::SignalContext;
+use std::time::Instant;
+use zbus::{InterfaceRef, MessageHeader, SignalContext};
 
+/// The service changes the properties of the server through shared references.
+///
+/// While a method awaits a reply from the service, the interface is held for
+/// reading, so the service must never wait to hold it for writing.
 pub struct Server {
+    /// The system config, from which several of the properties are derived.
+    pub config: Mutex<Config>,
+    /// Unix timestamp of the last check for updates.
+    pub last_check: AtomicU64,
+    pub last_update: Arc<AtomicU64>,
+    pub updates_available: AtomicBool,
     pub updating: Arc<AtomicBool>,
+    pub next_update: Arc<AtomicU64>,
     pub service: PopService<Event>,
+    pub authority: Authority,
+    /// Limits queries which any local user may make.
+    pub queries: RateLimit,
+}
+
+impl Server {
+    /// Changes the properties derived from the system config, and signals those that changed.
+    ///
+    /// # Errors
+    ///
+    /// Errors if a change could not be signaled.
+    pub async fn set_config(&self, ctx: &SignalContext<'_>, config: &Config) -> zbus::Result<()> {
+        let previous = std::mem::replace(&mut *self.config(), config.clone());
+
+        if previous.auto_update != config.auto_update {
+            self.auto_update_changed(ctx).await?;
+        }
+
+        if previous.schedule != config.schedule {
+            self.schedule_changed(ctx).await?;
+        }
+
+        if previous.policy != config.policy {
+            self.policy_changed(ctx).await?;
+        }
+
+        if previous.backends != config.backends {
+            self.backends_changed(ctx).await?;
+        }
+
+        Ok(())
+    }
+
+    /// # Errors
+    ///
+    /// Errors if the change could not be signaled.
+    pub async fn set_last_check(
+        &self,
+        ctx: &SignalContext<'_>,
+        last_check: u64,
+    ) -> zbus::Result<()> {
+        self.last_check.store(last_check, Ordering::SeqCst);
+        self.last_check_changed(ctx).await
+    }
+
+    /// # Errors
+    ///
+    /// Errors if the change could not be signaled.
+    pub async fn set_updates_available(
+        &self,
+        ctx: &SignalContext<'_>,
+        available: bool,
+    ) -> zbus::Result<()> {
+        if self.updates_available.swap(available, Ordering::SeqCst) == available {
+            return Ok(());
+        }
+
+        self.updates_available_changed(ctx).await
+    }
+
+    fn config(&self) -> MutexGuard<'_, Config> {
+        self.config.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Checks if the caller which sent a message is authorized to perform an action.
+    async fn authorize(&self, header: &MessageHeader<'_>, action: Action) -> zbus::fdo::Result<()> {
+        let sender = header.sender().ok().flatten();
+        self.authority.check(sender, action).await
+    }
+
+    /// Checks if the caller which sent a message may make another query.
+    fn limit(&self, header: &MessageHeader<'_>) -> zbus::fdo::Result<()> {
+        let sender = header.sender().ok().flatten();
+        let caller = sender.map_or("", |sender| sender.as_str());
+        self.queries.check(caller, Instant::now())
+    }
 }
 
 #[rustfmt::skip]
 #[dbus_interface(name = "com.system76.SystemUpdater")]
 impl Server {
-    async fn auto_update_set(&mut self, enable: bool) -> zbus::fdo::Result<()> {
-        self.service.send(Event::SetAutoUpdate(enable)).await
+    async fn auto_update_set(
+        &self,
+        enable: bool,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.authorize(&header, Action::SetAutoUpdate).await?;
+        self.service
+            .request(|reply| Event::SetAutoUpdate(enable, reply))
+            .await
+    }
+
+    /// Changes the package managers that the system is updated with, and how.
+    async fn backends_set(
+        &self,
+        backends: Backends,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.authorize(&header, Action::SetBackends).await?;
+        self.service
+            .request(|reply| Event::SetBackends(backends, reply))
+            .await
     }
 
     /// Check if any updates are available to install.
-    async fn check_for_updates(&mut self) -> zbus::fdo::Result<()> {
+    async fn check_for_updates(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.authorize(&header, Action::CheckForUpdates).await?;
         self.service.send(Event::CheckForUpdates).await
     }
 
+    /// Each key of the system config, the layer that set it, and if it is locked.
+    ///
+    /// Keys which a drop-in sets are reported as locked, as they cannot be changed here.
+    async fn config_layers(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<Vec<(String, String, bool)>> {
+        self.limit(&header)?;
+        self.service.request(Event::ConfigLayers).await
+    }
+
     /// Check if a system update is currently being performed.
     async fn is_updating(&self) -> bool {
         self.updating.load(Ordering::SeqCst)
     }
 
-    async fn repair(&mut self) -> zbus::fdo::Result<()> {
+    /// The updates available from each package manager, as of the last check for updates.
+    ///
+    /// Includes those which are only installed when the user asks, which are not `automatic`.
+    async fn list_updates(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<Vec<Available>> {
+        self.limit(&header)?;
+        self.service.request(Event::ListUpdates).await
+    }
+
+    async fn repair(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.authorize(&header, Action::Repair).await?;
         self.service.send(Event::Repair).await
     }
 
-    async fn update_scheduling_disable(&mut self) -> zbus::fdo::Result<()> {
-        self.service.send(Event::SetSchedule(None)).await
+    /// Reports of past system updates, from the most recent to the oldest.
+    ///
+    /// Skips the `offset` most recent reports, and returns no more than `limit`,
+    /// up to a page of `MAX_PAGE` reports.
+    async fn update_history(
+        &self,
+        offset: u32,
+        limit: u32,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<Vec<Report>> {
+        self.limit(&header)?;
+        let limit = (limit as usize).min(history::MAX_PAGE);
+        Ok(history::load(offset as usize, limit).await)
+    }
+
+    /// The output of the commands run by the update that started at the given time.
+    async fn update_log(
+        &self,
+        started: u64,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<String> {
+        self.authorize(&header, Action::ReadLog).await?;
+        history::load_log(started).await.ok_or_else(|| {
+            zbus::fdo::Error::FileNotFound(format!("no log for the update started at {}", started))
+        })
+    }
+
+    /// Postpones the next scheduled update by the given number of minutes.
+    async fn update_postpone(
+        &self,
+        minutes: u32,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.authorize(&header, Action::Defer).await?;
+        self.service
+            .request(|reply| Event::Postpone(minutes, reply))
+            .await
     }
 
-    async fn update_scheduling_set(&mut self, schedule: Schedule) -> zbus::fdo::Result<()> {
-        self.service.send(Event::SetSchedule(Some(schedule))).await
+    async fn update_scheduling_disable(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.authorize(&header, Action::SetSchedule).await?;
+        self.service
+            .request(|reply| Event::SetSchedule(None, reply))
+            .await
+    }
+
+    /// Schedules updates, on at least one day of the week and time of day.
+    async fn update_scheduling_set(
+        &self,
+        schedule: Schedule,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.authorize(&header, Action::SetSchedule).await?;
+        self.service
+            .request(|reply| Event::SetSchedule(Some(schedule), reply))
+            .await
+    }
+
+    /// Skips the next scheduled update, without changing the schedule.
+    async fn update_skip_next(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.authorize(&header, Action::Defer).await?;
+        self.service.request(Event::SkipNext).await
     }
 
     /// Initiates a system update.
-    async fn update_system(&mut self) -> zbus::fdo::Result<()> {
+    async fn update_system(
+        &self,
+        #[zbus(header)] header: MessageHeader<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.authorize(&header, Action::Update).await?;
         if !self.updating.load(Ordering::SeqCst) {
             self.service.send(Event::Update).await?;
         }
@@ -53,9 +260,90 @@ impl Server {
         Ok(())
     }
 
+    /// If updates are installed automatically.
+    #[dbus_interface(property)]
+    async fn auto_update(&self) -> bool {
+        self.config().auto_update
+    }
+
+    /// The package managers that the system is updated with, in order, and if they update one at a time.
+    #[dbus_interface(property)]
+    async fn backends(&self) -> Backends {
+        self.config().backends.clone()
+    }
+
+    /// If updates are available which a system update would install, as of the last check for updates.
+    #[dbus_interface(property, name = "UpdatesAvailable")]
+    async fn has_updates(&self) -> bool {
+        self.updates_available.load(Ordering::SeqCst)
+    }
+
+    /// Unix timestamp of the last check for updates, or 0 if not checked since the service started.
+    #[dbus_interface(property)]
+    async fn last_check(&self) -> u64 {
+        self.last_check.load(Ordering::SeqCst)
+    }
+
+    /// Unix timestamp of the last successful system update, or 0 if it never succeeded.
+    #[dbus_interface(property)]
+    async fn last_update(&self) -> u64 {
+        self.last_update.load(Ordering::SeqCst)
+    }
+
+    /// Unix timestamp of the next scheduled update, or 0 if none is scheduled.
+    #[dbus_interface(property)]
+    async fn next_scheduled_update(&self) -> u64 {
+        self.next_update.load(Ordering::SeqCst)
+    }
+
+    /// Restrictions placed on the settings by the admin, which clients should present as locked.
+    #[dbus_interface(property)]
+    async fn policy(&self) -> Policy {
+        self.config().policy.clone()
+    }
+
+    /// When updates are scheduled. Empty if they are installed as soon as they are available.
+    #[dbus_interface(property)]
+    async fn schedule(&self) -> Schedule {
+        self.config().schedule.clone().unwrap_or(Schedule {
+            days: BitFlags::empty(),
+            times: Vec::new(),
+            window: 0,
+        })
+    }
+
+    /// If a system update is currently being performed.
+    #[dbus_interface(property)]
+    async fn updating(&self) -> bool {
+        self.updating.load(Ordering::SeqCst)
+    }
+
+    /// An update from a source failed.
+    ///
+    /// The code is that of an `ErrorKind`. The command and its output are empty
+    /// if the error was not caused by a command. Only administrators receive it.
+    #[dbus_interface(signal)]
+    pub async fn error(
+        ctx: &SignalContext<'_>,
+        source: &str,
+        code: u32,
+        command: &str,
+        why: &str,
+        output: &str,
+    ) -> zbus::Result<()>;
+
+    /// A line of output that a command of a source wrote to `stdout` or `stderr`.
+    ///
+    /// Only administrators receive it.
     #[dbus_interface(signal)]
-    pub async fn error(ctx: &SignalContext<'_>, source: &str, why: &str) -> zbus::Result<()>;
+    pub async fn log(
+        ctx: &SignalContext<'_>,
+        source: &str,
+        stream: &str,
+        line: &str,
+    ) -> zbus::Result<()>;
 
+    /// The progress of a source during an update, or of the whole update if the source is `all`.
     #[dbus_interface(signal)]
     pub async fn progress(ctx: &SignalContext<'_>, source: &str, percent: u8) -> zbus::Result<()>;
 
@@ -65,6 +353,10 @@ impl Server {
     #[dbus_interface(signal)]
     pub async fn repair_ok(ctx: &SignalContext<'_>) -> zbus::Result<()>;
 
+    /// A system update finished, with a report of what it changed.
+    #[dbus_interface(signal)]
+    pub async fn update_finished(ctx: &SignalContext<'_>, report: &Report) -> zbus::Result<()>;
+
     #[dbus_interface(signal)]
     pub async fn updates_available(ctx: &SignalContext<'_>, available: bool) -> zbus::Result<()>;
 }
@@ -74,13 +366,124 @@ where
     C: FnOnce(SignalContext<'static>) -> F + 'a,
     F: Future<Output = zbus::Result<()>> + 'a,
 {
-    if let Ok(iface) = conn
-        .object_server()
-        .interface::<_, Server>(super::IFACE)
-        .await
-    {
+    if let Some(iface) = interface(conn).await {
         if let Err(why) = future(iface.signal_context().to_owned()).await {
             error!("context failed with {:?}", why);
         }
     }
 }
+
+/// The server that is being served on the connection, through which its properties are changed.
+pub async fn interface(conn: &zbus::Connection) -> Option<InterfaceRef<Server>> {
+    conn.object_server()
+        .interface::<_, Server>(super::IFACE)
+        .await
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dbus::IFACE;
+    use std::convert::TryFrom;
+    use std::time::Duration;
+    use zbus::{ConnectionBuilder, Guid};
+
+    /// Serves a server over a peer-to-peer connection, and returns a client's connection to it.
+    async fn serve(sender: flume::Sender<Event>) -> (zbus::Connection, zbus::Connection) {
+        let (service, client) = tokio::net::UnixStream::pair().unwrap();
+        let guid = Guid::generate();
+
+        let server = Server {
+            config: Mutex::new(Config::default()),
+            last_check: AtomicU64::new(0),
+            last_update: Arc::new(AtomicU64::new(0)),
+            updates_available: AtomicBool::new(false),
+            updating: Arc::new(AtomicBool::new(false)),
+            next_update: Arc::new(AtomicU64::new(0)),
+            service: PopService { sender },
+            authority: Authority::Local(BitFlags::<Action>::all()),
+            queries: RateLimit::new(),
+        };
+
+        let service = ConnectionBuilder::unix_stream(service)
+            .server(&guid)
+            .p2p()
+            .serve_at(IFACE, server)
+            .unwrap()
+            .build();
+
+        let client = ConnectionBuilder::unix_stream(client).p2p().build();
+
+        futures::try_join!(service, client).unwrap()
+    }
+
+    #[tokio::test]
+    async fn setters_reply_while_properties_change() {
+        let (sender, receiver) = flume::bounded(1);
+        let (service, client) = serve(sender).await;
+
+        // Like the service, changes the properties before replying to the request.
+        tokio::spawn(async move {
+            while let Ok(event) = receiver.recv_async().await {
+                if let Event::SetAutoUpdate(enable, reply) = event {
+                    let config = Config {
+                        auto_update: enable,
+                        ..Config::default()
+                    };
+
+                    let iface = interface(&service).await.unwrap();
+                    let ctx = iface.signal_context();
+                    iface.get().await.set_config(ctx, &config).await.unwrap();
+                    iface.get().await.set_last_check(ctx, 42).await.unwrap();
+                    iface
+                        .get()
+                        .await
+                        .set_updates_available(ctx, true)
+                        .await
+                        .unwrap();
+
+                    let _res = reply.send_async(Ok(())).await;
+                }
+            }
+        });
+
+        let enable = !Config::default().auto_update;
+
+        let args = (enable,);
+        let call = client.call_method(
+            None::<()>,
+            IFACE,
+            Some("com.system76.SystemUpdater"),
+            "AutoUpdateSet",
+            &args,
+        );
+
+        tokio::time::timeout(Duration::from_secs(5), call)
+            .await
+            .expect("request was not replied to")
+            .unwrap();
+
+        let property = |name: &'static str| {
+            let client = client.clone();
+            async move {
+                let reply = client
+                    .call_method(
+                        None::<()>,
+                        IFACE,
+                        Some("org.freedesktop.DBus.Properties"),
+                        "Get",
+                        &("com.system76.SystemUpdater", name),
+                    )
+                    .await
+                    .unwrap();
+
+                reply.body::<zvariant::OwnedValue>().unwrap()
+            }
+        };
+
+        assert_eq!(bool::try_from(property("AutoUpdate").await), Ok(enable));
+        assert_eq!(u64::try_from(property("LastCheck").await), Ok(42));
+        assert_eq!(bool::try_from(property("UpdatesAvailable").await), Ok(true));
+    }
+}
@@ -0,0 +1,491 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! Errors of the package managers, classified so that clients may offer remedies.
+//!
+//! Each package manager that is a [`Backend`] exits with its own codes, and
+//! writes its own messages, when it fails. Its exit code tells if it reported
+//! an error, and the errors that it wrote are then searched for the messages
+//! which it is known to write for each [`ErrorKind`]. Errors which are not
+//! recognized are of the `Other` kind.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::process::ExitStatus;
+use zvariant::Type;
+
+/// The number of lines of a failed command's output that are kept.
+pub const OUTPUT_LINES: usize = 20;
+
+/// What caused an update to fail. Sent over DBus as the code of the variant.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Type)]
+pub enum ErrorKind {
+    #[default]
+    Other = 0,
+    /// Package repositories could not be reached.
+    NoNetwork = 1,
+    /// Another package manager is holding the lock on the package database.
+    PackageLock = 2,
+    /// There was not enough space on the disk.
+    DiskFull = 3,
+    /// Packages have unmet dependencies, or were left partially installed.
+    BrokenDependencies = 4,
+    /// Packages that were held back by the admin would have been changed.
+    HeldPackages = 5,
+}
+
+/// Messages written for each kind of error, in lowercase, which are matched in order.
+type Patterns = &'static [(ErrorKind, &'static [&'static str])];
+
+/// A package manager whose failures are classified.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Backend {
+    Apt,
+    Dpkg,
+    Flatpak,
+    Fwupd,
+    Snap,
+}
+
+impl Backend {
+    /// apt exits with this whenever it reports an error.
+    const APT_FAILED: i32 = 100;
+
+    /// dpkg exits with this when packages could not be processed.
+    const DPKG_FAILED: i32 = 1;
+
+    /// dpkg exits with this on fatal errors, such as a locked database or a full disk.
+    const DPKG_FATAL: i32 = 2;
+
+    /// flatpak, fwupdmgr and snap exit with this when an operation failed. fwupdmgr
+    /// has other codes for when there was nothing to do, which are not faults.
+    const FAILED: i32 = 1;
+
+    /// Held packages are matched before broken dependencies, which apt also mentions.
+    const APT: Patterns = &[
+        (
+            ErrorKind::DiskFull,
+            &[
+                "you don't have enough free space",
+                "no space left on device",
+            ],
+        ),
+        (
+            ErrorKind::PackageLock,
+            &[
+                "could not get lock",
+                "unable to acquire the dpkg frontend lock",
+                "unable to lock the administration directory",
+                "unable to lock directory",
+            ],
+        ),
+        (
+            ErrorKind::NoNetwork,
+            &[
+                "temporary failure resolving",
+                "could not resolve",
+                "could not connect to",
+                "connection timed out",
+                "network is unreachable",
+                "failed to fetch",
+            ],
+        ),
+        (
+            ErrorKind::HeldPackages,
+            &["held packages were changed", "--allow-change-held-packages"],
+        ),
+        (
+            ErrorKind::BrokenDependencies,
+            &[
+                "unmet dependencies",
+                "you have held broken packages",
+                "dpkg was interrupted",
+                "broken packages",
+            ],
+        ),
+    ];
+
+    const DPKG: Patterns = &[
+        (ErrorKind::DiskFull, &["no space left on device"]),
+        (
+            ErrorKind::PackageLock,
+            &[
+                "unable to lock the administration directory",
+                "unable to acquire the dpkg frontend lock",
+            ],
+        ),
+        (
+            ErrorKind::BrokenDependencies,
+            &["dependency problems", "dpkg was interrupted"],
+        ),
+    ];
+
+    const FLATPAK: Patterns = &[
+        (
+            ErrorKind::DiskFull,
+            &["no space left on device", "not enough disk space"],
+        ),
+        (
+            ErrorKind::NoNetwork,
+            &[
+                "could not resolve hostname",
+                "temporary failure in name resolution",
+                "unable to connect",
+                "network is unreachable",
+                "timeout was reached",
+            ],
+        ),
+        (
+            ErrorKind::BrokenDependencies,
+            &["requires the runtime", "which was not found"],
+        ),
+    ];
+
+    const FWUPD: Patterns = &[
+        (
+            ErrorKind::DiskFull,
+            &["no space left on device", "insufficient space"],
+        ),
+        (
+            ErrorKind::NoNetwork,
+            &[
+                "failed to download",
+                "could not resolve",
+                "failed to connect",
+                "network is unreachable",
+            ],
+        ),
+    ];
+
+    const SNAP: Patterns = &[
+        (
+            ErrorKind::DiskFull,
+            &["no space left on device", "insufficient space"],
+        ),
+        (ErrorKind::PackageLock, &["change in progress"]),
+        (
+            ErrorKind::NoNetwork,
+            &[
+                "unable to contact snap store",
+                "temporary failure in name resolution",
+                "network is unreachable",
+            ],
+        ),
+    ];
+
+    /// The package manager that a command runs, by the name of its program.
+    #[must_use]
+    pub fn of(command: &str) -> Option<Self> {
+        let program = command.split_whitespace().next()?;
+
+        match Path::new(program).file_name()?.to_str()? {
+            "apt" | "apt-get" => Some(Backend::Apt),
+            "dpkg" => Some(Backend::Dpkg),
+            "flatpak" => Some(Backend::Flatpak),
+            "fwupdmgr" => Some(Backend::Fwupd),
+            "snap" => Some(Backend::Snap),
+            _ => None,
+        }
+    }
+
+    /// Classifies a failure of this package manager from its exit status, and the
+    /// errors that it wrote.
+    #[must_use]
+    pub fn classify(self, status: ExitStatus, output: &str) -> ErrorKind {
+        // A command that was killed by a signal did not report what went wrong.
+        let code = match status.code() {
+            Some(code) => code,
+            None => return ErrorKind::Other,
+        };
+
+        let patterns = match (self, code) {
+            (Backend::Apt, Self::APT_FAILED) => Self::APT,
+            (Backend::Dpkg, Self::DPKG_FAILED | Self::DPKG_FATAL) => Self::DPKG,
+            (Backend::Flatpak, Self::FAILED) => Self::FLATPAK,
+            (Backend::Fwupd, Self::FAILED) => Self::FWUPD,
+            (Backend::Snap, Self::FAILED) => Self::SNAP,
+            _ => return ErrorKind::Other,
+        };
+
+        let output = output.to_lowercase();
+
+        patterns
+            .iter()
+            .find(|(_, patterns)| patterns.iter().any(|p| output.contains(p)))
+            .map_or(ErrorKind::Other, |&(kind, _)| kind)
+    }
+}
+
+/// An error that occurred while updating.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Type)]
+pub struct UpdateError {
+    pub kind: ErrorKind,
+    /// The command that failed, or empty if the error was not caused by a command.
+    pub command: String,
+    /// The error, followed by each of its causes.
+    pub why: String,
+    /// The last lines of the output written by the command that failed.
+    pub output: String,
+}
+
+impl From<&anyhow::Error> for UpdateError {
+    /// Errors which were not caused by a command are only classified if they are
+    /// due to a full disk.
+    fn from(error: &anyhow::Error) -> Self {
+        let why = format!("{:#}", error);
+
+        let failed = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<CommandFailed>());
+
+        let (kind, command, output) = match failed {
+            Some(failed) => (failed.kind, failed.command.clone(), failed.output.clone()),
+            None => {
+                let disk_full = error
+                    .chain()
+                    .filter_map(|cause| cause.downcast_ref::<io::Error>())
+                    .any(|why| why.raw_os_error() == Some(libc::ENOSPC));
+
+                let kind = if disk_full {
+                    ErrorKind::DiskFull
+                } else {
+                    ErrorKind::Other
+                };
+
+                (kind, String::new(), String::new())
+            }
+        };
+
+        Self {
+            kind,
+            command,
+            why,
+            output,
+        }
+    }
+}
+
+impl fmt::Display for UpdateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.kind, self.why)
+    }
+}
+
+/// A command which exited unsuccessfully.
+#[derive(Debug)]
+pub struct CommandFailed {
+    pub command: String,
+    pub status: ExitStatus,
+    /// The last lines of the output that it wrote.
+    pub output: String,
+    /// The cause, as classified by the package manager that the command ran.
+    pub kind: ErrorKind,
+}
+
+impl CommandFailed {
+    #[must_use]
+    pub fn new(command: String, status: ExitStatus, output: &str) -> Self {
+        let kind = Backend::of(&command)
+            .map_or(ErrorKind::Other, |backend| backend.classify(status, output));
+
+        Self {
+            command,
+            status,
+            output: tail(output, OUTPUT_LINES),
+            kind,
+        }
+    }
+}
+
+impl fmt::Display for CommandFailed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "`{}` exited with {}", self.command, self.status)
+    }
+}
+
+impl std::error::Error for CommandFailed {}
+
+/// The last lines of some text.
+#[must_use]
+pub fn tail(text: &str, lines: usize) -> String {
+    let text = text.trim_end();
+    let start = text
+        .rmatch_indices('\n')
+        .nth(lines.saturating_sub(1))
+        .map_or(0, |(position, _)| position + 1);
+
+    text[start..].to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::process::ExitStatusExt;
+
+    fn exited(code: i32) -> ExitStatus {
+        ExitStatus::from_raw(code << 8)
+    }
+
+    #[test]
+    fn classified_by_backend_from_output() {
+        let cases = [
+            (
+                Backend::Apt,
+                "E: Could not get lock /var/lib/dpkg/lock-frontend. It is held by process 1234 (apt)",
+                ErrorKind::PackageLock,
+            ),
+            (
+                Backend::Apt,
+                "Err:1 http://archive.ubuntu.com jammy InRelease\n  Temporary failure resolving 'archive.ubuntu.com'",
+                ErrorKind::NoNetwork,
+            ),
+            (
+                Backend::Apt,
+                "E: You don't have enough free space in /var/cache/apt/archives/.",
+                ErrorKind::DiskFull,
+            ),
+            (
+                Backend::Apt,
+                "E: Held packages were changed and -y was used without --allow-change-held-packages.",
+                ErrorKind::HeldPackages,
+            ),
+            (
+                Backend::Apt,
+                "E: Unable to correct problems, you have held broken packages.",
+                ErrorKind::BrokenDependencies,
+            ),
+            (
+                Backend::Flatpak,
+                "error: Failed to install: No space left on device",
+                ErrorKind::DiskFull,
+            ),
+            (
+                Backend::Flatpak,
+                "error: While pulling app/org.gnome.Maps: Could not resolve hostname",
+                ErrorKind::NoNetwork,
+            ),
+            (
+                Backend::Snap,
+                "error: snap \"firefox\" has \"refresh\" change in progress",
+                ErrorKind::PackageLock,
+            ),
+            (
+                Backend::Fwupd,
+                "Failed to download https://cdn.fwupd.org/: Could not resolve host",
+                ErrorKind::NoNetwork,
+            ),
+            (Backend::Apt, "E: something unexpected", ErrorKind::Other),
+        ];
+
+        for (backend, output, kind) in &cases {
+            let code = if *backend == Backend::Apt { 100 } else { 1 };
+            assert_eq!(backend.classify(exited(code), output), *kind, "{}", output);
+        }
+    }
+
+    #[test]
+    fn classified_by_exit_code() {
+        let lock = "E: Could not get lock /var/lib/dpkg/lock-frontend";
+
+        assert_eq!(
+            Backend::Apt.classify(exited(100), lock),
+            ErrorKind::PackageLock
+        );
+
+        // apt only reports errors by exiting with 100.
+        assert_eq!(Backend::Apt.classify(exited(1), lock), ErrorKind::Other);
+
+        // Commands that were killed by a signal did not report an error.
+        assert_eq!(
+            Backend::Apt.classify(ExitStatus::from_raw(libc::SIGKILL), lock),
+            ErrorKind::Other
+        );
+
+        let dpkg = "dpkg: error: unable to lock the administration directory (/var/lib/dpkg/)";
+        assert_eq!(
+            Backend::Dpkg.classify(exited(2), dpkg),
+            ErrorKind::PackageLock
+        );
+
+        let dpkg = "dpkg: dependency problems prevent configuration of foo:";
+        assert_eq!(
+            Backend::Dpkg.classify(exited(1), dpkg),
+            ErrorKind::BrokenDependencies
+        );
+
+        // fwupdmgr exits with 2 when there is nothing to do, which is not a fault.
+        let fwupd = "No updatable devices: insufficient space";
+        assert_eq!(
+            Backend::Fwupd.classify(exited(1), fwupd),
+            ErrorKind::DiskFull
+        );
+        assert_eq!(Backend::Fwupd.classify(exited(2), fwupd), ErrorKind::Other);
+
+        let flatpak = "error: No space left on device";
+        assert_eq!(
+            Backend::Flatpak.classify(exited(1), flatpak),
+            ErrorKind::DiskFull
+        );
+        assert_eq!(
+            Backend::Flatpak.classify(exited(42), flatpak),
+            ErrorKind::Other
+        );
+    }
+
+    #[test]
+    fn backends_of_commands() {
+        assert_eq!(Backend::of("apt-get full-upgrade"), Some(Backend::Apt));
+        assert_eq!(
+            Backend::of("/usr/bin/dpkg --configure -a"),
+            Some(Backend::Dpkg)
+        );
+        assert_eq!(Backend::of("flatpak update -y"), Some(Backend::Flatpak));
+        assert_eq!(Backend::of("fwupdmgr refresh"), Some(Backend::Fwupd));
+        assert_eq!(Backend::of("snap refresh"), Some(Backend::Snap));
+        assert_eq!(Backend::of("nix-env --upgrade"), None);
+        assert_eq!(Backend::of(""), None);
+    }
+
+    #[test]
+    fn command_failures_are_found_in_the_chain() {
+        let failed = CommandFailed::new(
+            "apt-get full-upgrade".into(),
+            exited(100),
+            "E: Could not get lock /var/lib/dpkg/lock-frontend\n",
+        );
+
+        let error = anyhow::Error::new(failed).context("failed to install updates");
+        let error = UpdateError::from(&error);
+
+        assert_eq!(error.kind, ErrorKind::PackageLock);
+        assert_eq!(error.command, "apt-get full-upgrade");
+        assert_eq!(
+            error.why,
+            "failed to install updates: `apt-get full-upgrade` exited with exit status: 100"
+        );
+        assert_eq!(
+            error.output,
+            "E: Could not get lock /var/lib/dpkg/lock-frontend"
+        );
+    }
+
+    #[test]
+    fn full_disks_are_found_in_the_chain() {
+        let error = anyhow::Error::new(io::Error::from_raw_os_error(libc::ENOSPC))
+            .context("failed to write the report");
+
+        assert_eq!(UpdateError::from(&error).kind, ErrorKind::DiskFull);
+
+        let error = anyhow::anyhow!("no space left on device");
+        assert_eq!(UpdateError::from(&error).kind, ErrorKind::Other);
+    }
+
+    #[test]
+    fn tail_of_output() {
+        assert_eq!(tail("a\nb\nc\n", 2), "b\nc");
+        assert_eq!(tail("a\nb", 5), "a\nb");
+        assert_eq!(tail("", 5), "");
+    }
+}
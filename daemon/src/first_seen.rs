@@ -0,0 +1,98 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! Records when each of apt's updates was first seen, so that they may be deferred.
+//!
+//! Updates are recorded by their name and candidate version, and are forgotten
+//! once they are no longer available, such as after they were installed.
+
+use crate::config::{self, Versioned};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+pub const FIRST_SEEN_PATH: &str = "/var/cache/pop-system-updater/first-seen.ron";
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct FirstSeen {
+    /// The version of the format that the file was written in.
+    pub version: u32,
+    /// Seconds since the Unix epoch when each `name=version` was first seen.
+    pub updates: BTreeMap<String, u64>,
+}
+
+impl Default for FirstSeen {
+    fn default() -> Self {
+        Self {
+            version: Self::VERSION,
+            updates: BTreeMap::new(),
+        }
+    }
+}
+
+impl Versioned for FirstSeen {
+    /// 1. Updates by their name and version.
+    const VERSION: u32 = 1;
+
+    fn migrate(_version: Option<u32>, _file: &str) -> Option<Self> {
+        None
+    }
+}
+
+impl FirstSeen {
+    /// Records the updates which are available now, and forgets the rest.
+    pub fn observe(&mut self, updates: impl Iterator<Item = String>, now: u64) {
+        let previous = std::mem::take(&mut self.updates);
+
+        self.updates = updates
+            .map(|update| {
+                let seen = previous.get(&update).copied().unwrap_or(now);
+                (update, seen)
+            })
+            .collect();
+    }
+
+    /// When an update was first seen, if it was recorded.
+    #[must_use]
+    pub fn since(&self, update: &str) -> Option<u64> {
+        self.updates.get(update).copied()
+    }
+}
+
+/// The updates which were seen before, or none if nothing was recorded.
+pub async fn load() -> FirstSeen {
+    config::read_system_file(Path::new(FIRST_SEEN_PATH))
+        .await
+        .unwrap_or_default()
+}
+
+/// # Errors
+///
+/// Errors if the record could not be written.
+pub async fn write(first_seen: &FirstSeen) -> io::Result<()> {
+    config::write_system_file(Path::new(FIRST_SEEN_PATH), first_seen).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_keep_when_they_were_first_seen() {
+        let mut first_seen = FirstSeen::default();
+
+        first_seen.observe(
+            vec!["vim=2".to_owned(), "libc6=3".to_owned()].into_iter(),
+            100,
+        );
+        first_seen.observe(
+            vec!["vim=2".to_owned(), "libc6=4".to_owned()].into_iter(),
+            200,
+        );
+
+        assert_eq!(first_seen.since("vim=2"), Some(100));
+        assert_eq!(first_seen.since("libc6=4"), Some(200));
+        assert_eq!(first_seen.since("libc6=3"), None);
+    }
+}
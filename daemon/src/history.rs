@@ -0,0 +1,278 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! Reports of each system update, which are kept as a history of what changed.
+//!
+//! Each report is written to its own file in [`HISTORY_DIR`], which is named
+//! after the time that the update started. Only the most recent
+//! [`MAX_REPORTS`] are kept. The output of the commands that an update ran is
+//! kept beside its report, in a `.log` file of the same name.
+
+use crate::config::{self, Parsed, Versioned};
+use crate::error::UpdateError;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use zvariant::Type;
+
+pub const HISTORY_DIR: &str = "/var/lib/pop-system-updater/history";
+
+/// The number of reports that are kept before the oldest are removed.
+pub const MAX_REPORTS: usize = 100;
+
+/// The most reports that may be loaded by a single query.
+pub const MAX_PAGE: usize = 20;
+
+/// The outcome of a system update.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize, Type)]
+pub struct Report {
+    /// The version of the format that the file was written in.
+    pub version: u32,
+    /// Unix timestamp of when the update started.
+    pub started: u64,
+    /// Unix timestamp of when the update finished.
+    pub finished: u64,
+    /// Each package manager that was used to update the system.
+    pub sources: Vec<Source>,
+}
+
+impl Default for Report {
+    fn default() -> Self {
+        Self {
+            version: Self::VERSION,
+            started: 0,
+            finished: 0,
+            sources: Vec::new(),
+        }
+    }
+}
+
+impl Versioned for Report {
+    /// 1. Errors were descriptions.
+    /// 2. Errors are classified.
+    const VERSION: u32 = 2;
+
+    fn migrate(version: Option<u32>, file: &str) -> Option<Self> {
+        if version != Some(1) {
+            return None;
+        }
+
+        let report = ron::from_str::<v1::Report>(file).ok()?;
+
+        Some(Self {
+            version: Self::VERSION,
+            started: report.started,
+            finished: report.finished,
+            sources: report
+                .sources
+                .into_iter()
+                .map(|source| Source {
+                    name: source.name,
+                    packages: source.packages,
+                    errors: source
+                        .errors
+                        .into_iter()
+                        .map(|why| UpdateError {
+                            why,
+                            ..UpdateError::default()
+                        })
+                        .collect(),
+                })
+                .collect(),
+        })
+    }
+}
+
+impl Report {
+    /// Checks if every source updated without errors.
+    #[must_use]
+    pub fn succeeded(&self) -> bool {
+        self.sources.iter().all(Source::succeeded)
+    }
+}
+
+/// The outcome of updating with a package manager.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Type)]
+pub struct Source {
+    pub name: String,
+    /// Packages which were upgraded, if the package manager reports them.
+    pub packages: Vec<Package>,
+    pub errors: Vec<UpdateError>,
+}
+
+impl Source {
+    #[must_use]
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            ..Self::default()
+        }
+    }
+
+    #[must_use]
+    pub fn succeeded(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+/// A package which was upgraded, or newly installed.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Type)]
+pub struct Package {
+    pub name: String,
+    /// Empty if the package was not installed before.
+    pub old_version: String,
+    pub new_version: String,
+}
+
+/// Reports before errors were classified.
+mod v1 {
+    use super::Package;
+    use serde::Deserialize;
+
+    #[derive(Deserialize)]
+    pub struct Report {
+        pub started: u64,
+        pub finished: u64,
+        pub sources: Vec<Source>,
+    }
+
+    #[derive(Deserialize)]
+    pub struct Source {
+        pub name: String,
+        pub packages: Vec<Package>,
+        pub errors: Vec<String>,
+    }
+}
+
+/// Writes a report to the history, and removes the oldest reports beyond [`MAX_REPORTS`].
+///
+/// # Errors
+///
+/// Errors if the report could not be written.
+pub async fn write(report: &Report) -> io::Result<()> {
+    let path = Path::new(HISTORY_DIR).join(format!("{}.ron", report.started));
+    config::write_system_file(&path, report).await?;
+
+    for path in reports(Path::new(HISTORY_DIR))
+        .await
+        .into_iter()
+        .skip(MAX_REPORTS)
+    {
+        if let Err(why) = tokio::fs::remove_file(&path).await {
+            error!("failed to remove old report {:?}: {}", path, why);
+        }
+
+        let log = path.with_extension("log");
+        if let Err(why) = tokio::fs::remove_file(&log).await {
+            if why.kind() != io::ErrorKind::NotFound {
+                error!("failed to remove old log {:?}: {}", log, why);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads reports from the history, from the most recent to the oldest.
+///
+/// Skips the `offset` most recent reports, and reads no more than `limit`.
+/// Reports which cannot be read are skipped, and left as they are.
+pub async fn load(offset: usize, limit: usize) -> Vec<Report> {
+    load_from(Path::new(HISTORY_DIR), offset, limit).await
+}
+
+async fn load_from(dir: &Path, offset: usize, limit: usize) -> Vec<Report> {
+    let mut history = Vec::new();
+
+    for path in reports(dir).await.into_iter().skip(offset).take(limit) {
+        let file = match tokio::fs::read_to_string(&path).await {
+            Ok(file) => file,
+            Err(why) => {
+                error!("failed to read report {:?}: {}", path, why);
+                continue;
+            }
+        };
+
+        match config::parse::<Report>(&file) {
+            Parsed::Current(report) | Parsed::Migrated(report) => history.push(report),
+            Parsed::Newer(version) => {
+                info!("skipping report {:?} of newer version {}", path, version);
+            }
+            Parsed::Invalid(why) => error!("skipping invalid report {:?}: {}", path, why),
+        }
+    }
+
+    history
+}
+
+/// The file that the output of the update which started at the given time is written to.
+#[must_use]
+pub fn log_path(started: u64) -> PathBuf {
+    Path::new(HISTORY_DIR).join(format!("{}.log", started))
+}
+
+/// Reads the output of the update which started at the given time, if it was kept.
+pub async fn load_log(started: u64) -> Option<String> {
+    let log = tokio::fs::read(log_path(started)).await.ok()?;
+    Some(String::from_utf8_lossy(&log).into_owned())
+}
+
+/// The files of each report in a directory, from the most recent to the oldest.
+async fn reports(dir: &Path) -> Vec<PathBuf> {
+    let mut reports = Vec::new();
+
+    if let Ok(mut entries) = tokio::fs::read_dir(dir).await {
+        while let Ok(Some(entry)) = entries.next_entry().await {
+            let path = entry.path();
+            if path.extension() != Some("ron".as_ref()) {
+                continue;
+            }
+
+            let started = path
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .and_then(|stem| stem.parse::<u64>().ok());
+
+            if let Some(started) = started {
+                reports.push((started, path));
+            }
+        }
+    }
+
+    reports.sort_by_key(|&(started, _)| std::cmp::Reverse(started));
+    reports.into_iter().map(|(_, path)| path).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn invalid_reports_are_skipped_and_kept() {
+        let dir =
+            std::env::temp_dir().join(format!("pop-system-updater-history-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let report = Report {
+            started: 1,
+            finished: 2,
+            ..Report::default()
+        };
+
+        std::fs::write(dir.join("1.ron"), ron::to_string(&report).unwrap()).unwrap();
+        std::fs::write(dir.join("2.ron"), "(version: 2, started: ").unwrap();
+
+        let history = load_from(&dir, 0, MAX_PAGE).await;
+
+        let mut files = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<_>>();
+        files.sort();
+
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(history, vec![report]);
+        assert_eq!(files, ["1.ron", "2.ron"]);
+    }
+}
@@ -0,0 +1,102 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+use std::ffi::{CString, OsString};
+use std::io;
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::io::{AsRawFd, FromRawFd, OwnedFd};
+use std::path::Path;
+use tokio::io::unix::AsyncFd;
+
+/// Changes to files which are created, written, moved or removed.
+const MASK: u32 = libc::IN_CLOSE_WRITE | libc::IN_MOVED_TO | libc::IN_MOVED_FROM | libc::IN_DELETE;
+
+const HEADER: usize = std::mem::size_of::<libc::inotify_event>();
+
+/// Watches directories for changes to the files within them.
+pub struct Watcher {
+    fd: AsyncFd<OwnedFd>,
+}
+
+impl Watcher {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: the descriptor was just created, and is owned by nothing else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        Ok(Self {
+            fd: AsyncFd::new(fd)?,
+        })
+    }
+
+    /// Watches a directory, which must already exist.
+    pub fn watch(&self, directory: &Path) -> io::Result<()> {
+        let path = CString::new(directory.as_os_str().as_bytes())
+            .map_err(|why| io::Error::new(io::ErrorKind::InvalidInput, why))?;
+
+        let wd = unsafe { libc::inotify_add_watch(self.fd.as_raw_fd(), path.as_ptr(), MASK) };
+        if wd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(())
+    }
+
+    /// Waits for changes, and returns the names of the files that changed.
+    pub async fn changed(&self) -> io::Result<Vec<OsString>> {
+        let mut buffer = [0u8; 4096];
+
+        let read = loop {
+            let mut guard = self.fd.readable().await?;
+
+            let result = guard.try_io(|fd| {
+                let read = unsafe {
+                    libc::read(
+                        fd.as_raw_fd(),
+                        buffer.as_mut_ptr().cast::<libc::c_void>(),
+                        buffer.len(),
+                    )
+                };
+
+                if read == -1 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(read as usize)
+                }
+            });
+
+            if let Ok(result) = result {
+                break result?;
+            }
+        };
+
+        let mut names = Vec::new();
+        let mut offset = 0;
+
+        while offset + HEADER <= read {
+            // SAFETY: the kernel writes whole events, which may not be aligned in the buffer.
+            let event = unsafe {
+                std::ptr::read_unaligned(buffer[offset..].as_ptr().cast::<libc::inotify_event>())
+            };
+
+            let start = offset + HEADER;
+            let end = (start + event.len as usize).min(read);
+
+            // The name is padded with nul bytes.
+            let name = &buffer[start..end];
+            let name = name.split(|&byte| byte == 0).next().unwrap_or_default();
+
+            if !name.is_empty() {
+                names.push(OsString::from_vec(name.to_owned()));
+            }
+
+            offset = end;
+        }
+
+        Ok(names)
+    }
+}
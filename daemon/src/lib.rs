@@ -9,3 +9,9 @@ extern crate tracing;
 pub mod appcenter;
 pub mod config;
 pub mod dbus;
+pub mod error;
+pub mod first_seen;
+pub mod history;
+pub mod polkit;
+pub mod prefetch;
+pub mod updates;
@@ -5,8 +5,12 @@
 extern crate tracing;
 
 mod accounts;
+mod inotify;
+mod login1;
 mod notify;
 mod package_managers;
+mod progress;
+mod runner;
 mod service;
 mod signal_handler;
 mod utils;
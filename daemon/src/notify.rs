@@ -33,12 +33,14 @@ pub fn notify<F: FnOnce()>(summary: &str, body: &str, func: F) {
         });
 }
 
-pub fn updates_available() {
-    notify(
-        "System updates are available to install",
-        "Click here to view available updates",
-        || {
-            tokio::spawn(pop_system_updater::appcenter::show_updates());
-        },
-    );
+pub fn updates_available(count: usize) {
+    let summary = if count == 1 {
+        "1 system update is available to install".to_owned()
+    } else {
+        format!("{} system updates are available to install", count)
+    };
+
+    notify(&summary, "Click here to view available updates", || {
+        tokio::spawn(pop_system_updater::appcenter::show_updates());
+    });
 }
@@ -1,28 +1,177 @@
 // Copyright 2021-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MPL-2.0
 
+use super::apt_policy::{Phase, Phasing, Policy};
+use super::PackageManager;
+use crate::progress;
+use crate::runner::Runner;
 use crate::utils;
 use anyhow::Context;
 use apt_cmd::lock::apt_lock_wait;
 use apt_cmd::{AptGet, AptMark, Dpkg};
+use async_trait::async_trait;
+use chrono::Utc;
 use futures::Stream;
 use futures::StreamExt;
+use pop_system_updater::config::AptPolicy;
+use pop_system_updater::error::CommandFailed;
+use pop_system_updater::first_seen;
+use pop_system_updater::updates::Update;
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
 use std::pin::Pin;
 use std::process::Stdio;
+use tokio::io::AsyncReadExt;
 use tokio::process::{Child, Command};
 
-pub async fn update(conn: zbus::Connection) -> bool {
-    const SOURCE: &str = "apt";
+const SOURCE: &str = "apt";
 
-    if !utils::command_exists(SOURCE) {
-        return false;
+/// Phasing is decided by the policy, as apt only phases updates on newer releases.
+const INCLUDE_PHASED: [&str; 2] = ["-o", "APT::Get::Always-Include-Phased-Updates=true"];
+
+/// The admin's apt preferences, which holds are pinned alongside.
+const PREFERENCES: &str = "/etc/apt/preferences";
+
+/// Where the preferences of a single invocation of apt are written.
+const PREFERENCES_DIR: &str = "/run/pop-system-updater";
+
+/// Packages which may be upgraded, and those which are held back at their installed versions.
+type Candidates = (Vec<Update>, Vec<String>);
+
+/// Debian packages from the system's apt repositories.
+pub struct Apt {
+    policy: AptPolicy,
+    /// The candidates as of the last refresh, which are found once for each update.
+    candidates: tokio::sync::Mutex<Option<Candidates>>,
+}
+
+impl Apt {
+    pub fn new(policy: AptPolicy) -> Self {
+        Self {
+            policy,
+            candidates: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// The packages which may be upgraded, and those which are held back, as `name=version`.
+    async fn candidates(&self, runner: &Runner) -> anyhow::Result<Candidates> {
+        let mut candidates = self.candidates.lock().await;
+
+        if candidates.is_none() {
+            *candidates = Some(self.find_candidates(runner).await?);
+        }
+
+        Ok(candidates.clone().unwrap_or_default())
+    }
+
+    async fn find_candidates(&self, runner: &Runner) -> anyhow::Result<Candidates> {
+        info!("getting list of packages to update");
+        let mut packages = packages_to_fetch()
+            .await
+            .context("could not get packages to fetch")?;
+
+        let mut held = Vec::new();
+
+        // The service is held back while upgrading, so that it is not restarted midway.
+        if let Some(id) = packages.iter().position(|p| p.name == "pop-system-updater") {
+            info!("service requires update");
+            held.push(installed_key(&packages.swap_remove(id)));
+        }
+
+        // Without details, the phase of each update is unknown, and it is held back
+        // if phasing is enabled.
+        let details = match show(runner, &packages).await {
+            Ok(details) => details,
+            Err(why) => {
+                error!("{}: could not get details of packages: {:#}", SOURCE, why);
+                String::new()
+            }
+        };
+
+        let stanzas = parse_show(&details);
+
+        for package in &mut packages {
+            if let Some(stanza) = stanza_of(&stanzas, package) {
+                package.download_size = stanza.size;
+            }
+        }
+
+        let now = u64::try_from(Utc::now().timestamp()).unwrap_or(0);
+
+        let mut first_seen = first_seen::load().await;
+        first_seen.observe(packages.iter().map(version_key), now);
+
+        if let Err(why) = first_seen::write(&first_seen).await {
+            error!("failed to record when updates were first seen: {}", why);
+        }
+
+        let policy = Policy::new(&self.policy, now);
+
+        packages.retain(|package| {
+            let phasing = match stanza_of(&stanzas, package) {
+                Some(stanza) => stanza.phase().map_or(Phasing::Unphased, Phasing::Phased),
+                None => Phasing::Unknown,
+            };
+
+            let seen = first_seen.since(&version_key(package)).unwrap_or(now);
+
+            match policy.hold(package, phasing, seen) {
+                Some(reason) => {
+                    info!("{}: holding back {}: {}", SOURCE, package.name, reason);
+                    held.push(installed_key(package));
+                    false
+                }
+                None => true,
+            }
+        });
+
+        Ok((packages, held))
     }
+}
 
-    info!("performing system update with apt");
+#[async_trait]
+impl PackageManager for Apt {
+    fn name(&self) -> &str {
+        SOURCE
+    }
 
-    let mut service_requires_update = false;
+    fn is_available(&self) -> bool {
+        utils::command_exists(SOURCE)
+    }
+
+    async fn refresh(&self, runner: &Runner) -> anyhow::Result<()> {
+        update_package_lists(runner).await;
+        *self.candidates.lock().await = None;
+        Ok(())
+    }
 
-    if system_update(&mut service_requires_update).await.is_err() {
+    async fn pending(&self, runner: &Runner) -> anyhow::Result<Vec<Update>> {
+        let (packages, _held) = self.candidates(runner).await?;
+        Ok(packages)
+    }
+
+    async fn download(&self, runner: &Runner) -> anyhow::Result<Vec<String>> {
+        let (_packages, held) = self.candidates(runner).await?;
+
+        download(runner, &held)
+            .await
+            .context("could not download packages")?;
+
+        Ok(held)
+    }
+
+    fn downloads_ahead(&self) -> bool {
+        true
+    }
+
+    async fn apply(&self, runner: &Runner, held: &[String]) -> anyhow::Result<()> {
+        upgrade(runner, held)
+            .await
+            .context("could not upgrade packages")
+    }
+
+    /// Restores the system's sources, then repairs packages, trying up to three times.
+    async fn repair(&self, runner: &Runner) -> anyhow::Result<()> {
         if let Ok(release) = os_release::OS_RELEASE.as_ref() {
             if release.name == "Pop!_OS" {
                 let _res = super::apt_pop::regenerate(&release.version_codename).await;
@@ -30,37 +179,35 @@ pub async fn update(conn: zbus::Connection) -> bool {
         }
 
         let mut count = 0;
-        while let Err(why) = repair().await {
-            if count == 2 {
-                utils::error_handler(&conn, SOURCE, why).await;
-                return false;
+        loop {
+            match repair(runner).await {
+                Err(_) if count < 2 => count += 1,
+                result => return result,
             }
-
-            count += 1;
         }
     }
-
-    info!("{}: updated software for system", SOURCE);
-    service_requires_update
 }
 
-pub async fn repair() -> anyhow::Result<()> {
+pub async fn repair(runner: &Runner) -> anyhow::Result<()> {
     let _res = AptMark::new().hold(["pop-system-updater"]).await;
 
     apt_lock_wait().await;
-    let apt_get_result = AptGet::new()
+    let mut fix_broken = AptGet::new()
         .noninteractive()
         .fix_broken()
         .force()
-        .allow_downgrades()
-        .status()
+        .allow_downgrades();
+
+    let apt_get_result = runner
+        .run(SOURCE, &mut fix_broken, None)
         .await
         .context("failed to repair broken packages with `apt-get install -f`");
 
     apt_lock_wait().await;
-    let dpkg_result = Dpkg::new()
-        .configure_all()
-        .status()
+    let mut configure = Dpkg::new().configure_all();
+
+    let dpkg_result = runner
+        .run(SOURCE, &mut configure, None)
         .await
         .context("failed to configure packages with `dpkg --configure -a`");
 
@@ -69,32 +216,15 @@ pub async fn repair() -> anyhow::Result<()> {
     apt_get_result.and(dpkg_result)
 }
 
-async fn system_update(service_requires_update: &mut bool) -> anyhow::Result<()> {
-    update_package_lists().await;
-
-    info!("getting list of packages to update");
-    let packages = packages_to_fetch()
-        .await
-        .context("could not get packages to fetch")?;
-
-    let mut packages: Vec<&str> = packages.iter().map(String::as_str).collect();
-
-    if let Some(id) = packages.iter().position(|&p| p == "pop-system-updater") {
-        info!("service requires update");
-        *service_requires_update = true;
-        packages.swap_remove(id);
-    }
-
-    upgrade().await.context("could not upgrade packages")?;
-
-    Ok(())
-}
-
-pub async fn update_package_lists() {
+pub async fn update_package_lists(runner: &Runner) {
     info!("updating package lists");
     apt_lock_wait().await;
-    let result = AptGet::new()
-        .update()
+
+    let mut command = AptGet::new();
+    command.arg("update");
+
+    let result = runner
+        .run(SOURCE, &mut command, None)
         .await
         .context("could not `apt update` package lists");
 
@@ -103,46 +233,94 @@ pub async fn update_package_lists() {
     }
 }
 
-pub async fn packages_to_fetch() -> anyhow::Result<Vec<String>> {
+pub async fn packages_to_fetch() -> anyhow::Result<Vec<Update>> {
     apt_lock_wait().await;
 
     let (mut child, packages) = upgradable_packages()
         .await
         .context("could not get system updates from apt")?;
 
-    let packages = packages.collect::<Vec<String>>().await;
+    let mut stderr = child.stderr.take().context("stderr was not captured")?;
+    let mut errors = Vec::new();
+
+    // Errors are read alongside the packages, so that neither pipe fills up and blocks apt.
+    let (packages, _) = futures::join!(
+        packages.collect::<Vec<Update>>(),
+        stderr.read_to_end(&mut errors)
+    );
 
     info!("debian packages requiring updates: {}", packages.len());
 
-    child
+    let status = child
         .wait()
         .await
         .context("could not check for updates from apt")?;
 
+    if !status.success() {
+        let output = String::from_utf8_lossy(&errors);
+        return Err(CommandFailed::new(
+            "apt-get full-upgrade --dry-run".into(),
+            status,
+            &output,
+        ))
+        .context("could not check for updates from apt");
+    }
+
     Ok(packages)
 }
 
-pub async fn upgrade() -> anyhow::Result<()> {
+/// Downloads the packages of an upgrade into apt's cache, without installing them.
+///
+/// The `held` packages are kept at their installed versions.
+pub async fn download(runner: &Runner, held: &[String]) -> anyhow::Result<()> {
     apt_lock_wait().await;
 
-    let _res = AptMark::new().hold(["pop-system-updater"]).await;
+    let preferences = Preferences::pin("download", held).await?;
 
-    let mut result = AptGet::new()
-        .noninteractive()
-        .force()
-        .allow_downgrades()
-        .upgrade()
+    let mut command = AptGet::new().noninteractive().force().allow_downgrades();
+    command
+        .args(INCLUDE_PHASED)
+        .args(preferences.options())
+        .args(["-o", "APT::Status-Fd=1", "--download-only", "full-upgrade"]);
+
+    let result = runner
+        .run(SOURCE, &mut command, Some(progress::apt_status))
+        .await
+        .context("failed to download updates");
+
+    preferences.remove().await;
+
+    result
+}
+
+/// Upgrades packages from those which were downloaded, while reporting the
+/// progress that apt writes to `APT::Status-Fd`.
+///
+/// Fails instead of downloading packages which are missing from apt's cache.
+/// The `held` packages are kept at their installed versions.
+pub async fn upgrade(runner: &Runner, held: &[String]) -> anyhow::Result<()> {
+    apt_lock_wait().await;
+
+    let preferences = Preferences::pin("upgrade", held).await?;
+
+    let mut command = AptGet::new().noninteractive().force().allow_downgrades();
+    command
+        .args(INCLUDE_PHASED)
+        .args(preferences.options())
+        .args(["-o", "APT::Status-Fd=1", "--no-download", "full-upgrade"]);
+
+    let mut result = runner
+        .run(SOURCE, &mut command, Some(progress::apt_status))
         .await
         .context("failed to install updates");
 
-    let _res = AptMark::new().unhold(["pop-system-updater"]).await;
+    preferences.remove().await;
 
     if result.is_ok() {
-        result = AptGet::new()
-            .noninteractive()
-            .autoremove()
-            .force()
-            .status()
+        let mut autoremove = AptGet::new().noninteractive().autoremove().force();
+
+        result = runner
+            .run(SOURCE, &mut autoremove, None)
             .await
             .context("failed to autoremove packages");
     }
@@ -150,14 +328,16 @@ pub async fn upgrade() -> anyhow::Result<()> {
     result
 }
 
-pub type Packages = Pin<Box<dyn Stream<Item = String> + Send>>;
+pub type Packages = Pin<Box<dyn Stream<Item = Update> + Send>>;
 
 // Fetch all upgradeable debian packages from system apt repositories.
+// The errors of the child are piped, and must be read by the caller.
 pub async fn upgradable_packages() -> anyhow::Result<(Child, Packages)> {
     let mut child = Command::new("apt-get")
+        .args(INCLUDE_PHASED)
         .args(&["full-upgrade", "--dry-run"])
         .stdout(Stdio::piped())
-        .stderr(Stdio::null())
+        .stderr(Stdio::piped())
         .spawn()
         .context("failed to launch `apt`")?;
 
@@ -173,11 +353,8 @@ pub async fn upgradable_packages() -> anyhow::Result<(Child, Packages)> {
                 break
             }
 
-            let mut words = buffer.split_ascii_whitespace();
-            if let Some("Inst") = words.next() {
-                if let Some(package) = words.next() {
-                    yield package.into();
-                }
+            if let Some(package) = parse_inst(&buffer) {
+                yield package;
             }
 
             buffer.clear();
@@ -186,3 +363,302 @@ pub async fn upgradable_packages() -> anyhow::Result<(Child, Packages)> {
 
     Ok((child, stream))
 }
+
+/// Parses a package to be installed from the output of `apt-get --dry-run`.
+///
+/// The candidate is a security update if it is from a `-security` pocket.
+///
+/// ```text
+/// Inst libc6 [2.35-0ubuntu3] (2.35-0ubuntu3.1 Ubuntu:22.04/jammy-updates, Ubuntu:22.04/jammy-security [amd64])
+/// ```
+fn parse_inst(line: &str) -> Option<Update> {
+    let mut words = line.split_ascii_whitespace();
+
+    if words.next()? != "Inst" {
+        return None;
+    }
+
+    let mut update = Update {
+        name: words.next()?.to_owned(),
+        ..Update::default()
+    };
+
+    for word in words {
+        if !update.candidate_version.is_empty() {
+            // The releases that the candidate is available from.
+            let pocket = pocket(word.trim_end_matches(','));
+            update.security |= matches!(pocket, Some(pocket) if pocket.ends_with("-security"));
+        } else if let Some(version) = word.strip_prefix('[').and_then(|w| w.strip_suffix(']')) {
+            update.current_version = version.to_owned();
+        } else if let Some(version) = word.strip_prefix('(') {
+            update.candidate_version = version.to_owned();
+        }
+    }
+
+    Some(update)
+}
+
+/// The pocket of an origin that a candidate is available from, if it names one.
+///
+/// ```text
+/// Ubuntu:22.04/jammy-security
+/// ```
+fn pocket(origin: &str) -> Option<&str> {
+    let (_label, release) = origin.split_once(':')?;
+    let (_version, pocket) = release.split_once('/')?;
+    Some(pocket)
+}
+
+/// Preferences which pin held packages to their installed versions for a single invocation of apt.
+///
+/// Holds are not marked with `apt-mark`, so that they are never left behind if
+/// the service stops midway, and so that the admin's own holds are kept.
+struct Preferences {
+    path: Option<PathBuf>,
+}
+
+impl Preferences {
+    /// Writes the admin's preferences, after pins of each `name=version` which is `held`.
+    async fn pin(purpose: &str, held: &[String]) -> anyhow::Result<Self> {
+        if held.is_empty() {
+            return Ok(Self { path: None });
+        }
+
+        let mut preferences = pins(held);
+
+        if let Ok(admin) = tokio::fs::read_to_string(PREFERENCES).await {
+            preferences.push_str(&admin);
+        }
+
+        let path = Path::new(PREFERENCES_DIR).join(format!("apt-preferences-{}", purpose));
+
+        let write = async {
+            tokio::fs::create_dir_all(PREFERENCES_DIR).await?;
+            tokio::fs::write(&path, preferences).await
+        };
+
+        write
+            .await
+            .with_context(|| format!("could not write apt preferences to {:?}", path))?;
+
+        Ok(Self { path: Some(path) })
+    }
+
+    /// The options which give apt these preferences instead of the admin's.
+    fn options(&self) -> Vec<String> {
+        match self.path {
+            Some(ref path) => vec![
+                "-o".to_owned(),
+                format!("Dir::Etc::Preferences={}", path.display()),
+            ],
+            None => Vec::new(),
+        }
+    }
+
+    async fn remove(self) {
+        if let Some(path) = self.path {
+            let _res = tokio::fs::remove_file(path).await;
+        }
+    }
+}
+
+/// Pins each `name=version` to that version, above any version which apt would upgrade to.
+fn pins(held: &[String]) -> String {
+    held.iter()
+        .filter_map(|key| key.split_once('='))
+        .map(|(name, version)| {
+            let name = name.split(':').next().unwrap_or(name);
+            format!(
+                "Package: {}\nPin: version {}\nPin-Priority: 1001\n\n",
+                name, version
+            )
+        })
+        .collect()
+}
+
+/// A package by its name and installed version, which it is held back at.
+fn installed_key(package: &Update) -> String {
+    [&package.name, "=", &package.current_version].concat()
+}
+
+/// An update by its name and candidate version, as in `apt-get install name=version`.
+fn version_key(package: &Update) -> String {
+    [&package.name, "=", &package.candidate_version].concat()
+}
+
+/// Describes the candidate of each package with `apt-cache show`.
+async fn show(runner: &Runner, packages: &[Update]) -> anyhow::Result<String> {
+    if packages.is_empty() {
+        return Ok(String::new());
+    }
+
+    let versions: Vec<String> = packages.iter().map(version_key).collect();
+
+    let mut args = vec!["apt-cache", "show", "--no-all-versions"];
+    args.extend(versions.iter().map(String::as_str));
+
+    let output = runner.output(SOURCE, &args).await?;
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// A package's candidate, as described by `apt-cache show`.
+#[derive(Debug, Default, PartialEq, Eq)]
+struct Stanza<'a> {
+    package: &'a str,
+    version: &'a str,
+    /// The source package, and its version if it differs: `glibc (2.35-0ubuntu3)`.
+    source: Option<&'a str>,
+    /// Bytes of the package's archive.
+    size: u64,
+    phased_update_percentage: Option<u8>,
+}
+
+impl<'a> Stanza<'a> {
+    /// The phase that the candidate is being released in, if it is phased.
+    fn phase(&self) -> Option<Phase<'a>> {
+        let percentage = self.phased_update_percentage?;
+        let mut source = self.source.unwrap_or(self.package).split_ascii_whitespace();
+
+        Some(Phase {
+            source: source.next().unwrap_or(self.package),
+            source_version: source
+                .next()
+                .map(|version| version.trim_matches(|c| c == '(' || c == ')'))
+                .unwrap_or(self.version),
+            percentage,
+        })
+    }
+}
+
+fn stanza_of<'a, 'b>(stanzas: &'b [Stanza<'a>], package: &Update) -> Option<&'b Stanza<'a>> {
+    stanzas.iter().find(|stanza| {
+        stanza.version == package.candidate_version
+            && package.name.split(':').next() == Some(stanza.package)
+    })
+}
+
+/// Parses each stanza in the output of `apt-cache show`.
+fn parse_show(output: &str) -> Vec<Stanza<'_>> {
+    output
+        .split("\n\n")
+        .filter_map(|stanza| {
+            let (mut package, mut version) = (None, None);
+            let mut parsed = Stanza::default();
+
+            for line in stanza.lines() {
+                let (field, value) = match line.split_once(':') {
+                    Some((field, value)) => (field, value.trim()),
+                    None => continue,
+                };
+
+                match field {
+                    "Package" => package = Some(value),
+                    "Version" => version = Some(value),
+                    "Source" => parsed.source = Some(value),
+                    "Size" => parsed.size = value.parse().unwrap_or(0),
+                    "Phased-Update-Percentage" => {
+                        parsed.phased_update_percentage = value.parse().ok();
+                    }
+                    _ => (),
+                }
+            }
+
+            Some(Stanza {
+                package: package?,
+                version: version?,
+                ..parsed
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn inst_lines() {
+        let package = parse_inst(
+            "Inst libc6 [2.35-0ubuntu3] (2.35-0ubuntu3.1 Ubuntu:22.04/jammy-updates [amd64])",
+        )
+        .unwrap();
+
+        assert_eq!(package.name, "libc6");
+        assert_eq!(package.current_version, "2.35-0ubuntu3");
+        assert_eq!(package.candidate_version, "2.35-0ubuntu3.1");
+        assert!(!package.security);
+
+        let package =
+            parse_inst("Inst linux-image-6.0.6 (6.0.6.202210290932 Pop:22.04 [amd64])").unwrap();
+
+        assert!(package.current_version.is_empty());
+        assert_eq!(package.candidate_version, "6.0.6.202210290932");
+
+        let package = parse_inst(
+            "Inst openssl [3.0.2-0ubuntu1.6] (3.0.2-0ubuntu1.7 Ubuntu:22.04/jammy-updates, Ubuntu:22.04/jammy-security [amd64])",
+        )
+        .unwrap();
+
+        assert_eq!(package.candidate_version, "3.0.2-0ubuntu1.7");
+        assert!(package.security);
+
+        assert!(parse_inst("Conf libc6 (2.35-0ubuntu3.1 Ubuntu:22.04 [amd64])").is_none());
+    }
+
+    #[test]
+    fn stanzas_from_apt_cache() {
+        let output = "Package: libc6\nArchitecture: amd64\nVersion: 2.35-0ubuntu3.1\nSource: glibc\nSize: 3235780\n\nPackage: vim\nVersion: 2:8.2.3995-1ubuntu2.1\nPhased-Update-Percentage: 30\nSize: 1729620\nDescription: Vi IMproved\n\nPackage: libnss3\nVersion: 2:3.68.2-0ubuntu1.1\nSource: nss (2:3.68.2-0ubuntu1)\nPhased-Update-Percentage: 10\n";
+
+        let stanzas = parse_show(output);
+
+        assert_eq!(stanzas.len(), 3);
+        assert_eq!(stanzas[0].package, "libc6");
+        assert_eq!(stanzas[0].version, "2.35-0ubuntu3.1");
+        assert_eq!(stanzas[0].size, 3_235_780);
+        assert_eq!(stanzas[0].phase(), None);
+        assert_eq!(stanzas[1].size, 1_729_620);
+
+        assert_eq!(
+            stanzas[1].phase(),
+            Some(Phase {
+                source: "vim",
+                source_version: "2:8.2.3995-1ubuntu2.1",
+                percentage: 30
+            })
+        );
+
+        assert_eq!(
+            stanzas[2].phase(),
+            Some(Phase {
+                source: "nss",
+                source_version: "2:3.68.2-0ubuntu1",
+                percentage: 10
+            })
+        );
+    }
+
+    #[test]
+    fn holds_are_pinned_at_installed_versions() {
+        let held = vec![
+            "vim=2:8.2.3995-1ubuntu2".to_owned(),
+            "libc6:i386=2.35-0ubuntu3".to_owned(),
+        ];
+
+        assert_eq!(
+            pins(&held),
+            "Package: vim\nPin: version 2:8.2.3995-1ubuntu2\nPin-Priority: 1001\n\n\
+             Package: libc6\nPin: version 2.35-0ubuntu3\nPin-Priority: 1001\n\n"
+        );
+        assert!(pins(&[]).is_empty());
+    }
+
+    #[test]
+    fn pockets_of_origins() {
+        assert_eq!(
+            pocket("Ubuntu:22.04/jammy-security"),
+            Some("jammy-security")
+        );
+        assert_eq!(pocket("Pop:22.04"), None);
+    }
+}
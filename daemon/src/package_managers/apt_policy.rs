@@ -0,0 +1,241 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! Decides which of apt's updates are held back by the config's [`AptPolicy`].
+//!
+//! Packages which are newly installed as dependencies are never held back, as
+//! holding them would also hold back the updates which depend on them.
+//!
+//! Updates whose phase could not be determined are held back while phasing is
+//! enabled, rather than being released to every system.
+
+use pop_system_updater::config::AptPolicy;
+use pop_system_updater::updates::Update;
+use std::fmt;
+
+const MACHINE_ID: &str = "/etc/machine-id";
+
+const SECONDS_PER_DAY: u64 = 86_400;
+
+/// Why an update is held back.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hold {
+    NotSecurity,
+    Deferred,
+    Phased,
+    PhaseUnknown,
+}
+
+impl fmt::Display for Hold {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Hold::NotSecurity => "not a security update",
+            Hold::Deferred => "deferred",
+            Hold::Phased => "not yet phased in",
+            Hold::PhaseUnknown => "its phase is unknown",
+        })
+    }
+}
+
+/// The phase that an update is being released to systems in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Phase<'a> {
+    pub source: &'a str,
+    pub source_version: &'a str,
+    /// The percentage of systems that the update is released to.
+    pub percentage: u8,
+}
+
+/// What is known of the phase that an update is being released in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phasing<'a> {
+    /// Released to every system.
+    Unphased,
+    Phased(Phase<'a>),
+    /// The update's details could not be read.
+    Unknown,
+}
+
+/// The config's policy, as it applies to this system at this time.
+pub struct Policy<'a> {
+    config: &'a AptPolicy,
+    machine_id: String,
+    now: u64,
+}
+
+impl<'a> Policy<'a> {
+    pub fn new(config: &'a AptPolicy, now: u64) -> Self {
+        let machine_id = std::fs::read_to_string(MACHINE_ID)
+            .map(|id| id.trim().to_owned())
+            .unwrap_or_default();
+
+        Self {
+            config,
+            machine_id,
+            now,
+        }
+    }
+
+    /// Why an update which was first seen at `first_seen` is held back, if it is.
+    ///
+    /// Security updates are never deferred or phased.
+    pub fn hold(&self, update: &Update, phasing: Phasing, first_seen: u64) -> Option<Hold> {
+        if update.current_version.is_empty() || update.security {
+            return None;
+        }
+
+        if self.config.security_only {
+            return Some(Hold::NotSecurity);
+        }
+
+        let deferral = u64::from(self.config.defer_days) * SECONDS_PER_DAY;
+        if self.now.saturating_sub(first_seen) < deferral {
+            return Some(Hold::Deferred);
+        }
+
+        if !self.config.phased {
+            return None;
+        }
+
+        match phasing {
+            Phasing::Phased(phase) if !in_phase(&self.machine_id, phase) => Some(Hold::Phased),
+            Phasing::Unknown => Some(Hold::PhaseUnknown),
+            _ => None,
+        }
+    }
+}
+
+/// Checks if the system is among the percentage of systems that an update is released to.
+///
+/// Each system has a stable position in the phase of each version of a source
+/// package, so that every binary package built from it is phased in at once.
+fn in_phase(machine_id: &str, phase: Phase) -> bool {
+    let seed = [phase.source, "-", phase.source_version, "-", machine_id].concat();
+
+    // FNV-1a, which unlike the standard library's hasher is the same in every release.
+    let hash = seed.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+
+    hash % 100 < u64::from(phase.percentage)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn update(security: bool) -> Update {
+        Update {
+            name: "vim".into(),
+            current_version: "2:8.2.3995-1ubuntu2".into(),
+            candidate_version: "2:8.2.3995-1ubuntu2.1".into(),
+            security,
+            ..Update::default()
+        }
+    }
+
+    fn on_machine(config: &AptPolicy) -> Policy<'_> {
+        Policy {
+            config,
+            machine_id: "b08dfa6083e7567a1921a715000001fb".into(),
+            now: 10 * SECONDS_PER_DAY,
+        }
+    }
+
+    #[test]
+    fn security_only() {
+        let config = AptPolicy {
+            security_only: true,
+            ..AptPolicy::default()
+        };
+
+        let policy = on_machine(&config);
+
+        assert_eq!(
+            policy.hold(&update(false), Phasing::Unphased, 0),
+            Some(Hold::NotSecurity)
+        );
+        assert_eq!(policy.hold(&update(true), Phasing::Unphased, 0), None);
+
+        let new_dependency = Update {
+            current_version: String::new(),
+            ..update(false)
+        };
+
+        assert_eq!(policy.hold(&new_dependency, Phasing::Unphased, 0), None);
+    }
+
+    #[test]
+    fn deferred_until_days_after_first_seen() {
+        let config = AptPolicy {
+            defer_days: 7,
+            ..AptPolicy::default()
+        };
+
+        let policy = on_machine(&config);
+
+        assert_eq!(policy.hold(&update(false), Phasing::Unphased, 0), None);
+        assert_eq!(
+            policy.hold(&update(false), Phasing::Unphased, 4 * SECONDS_PER_DAY),
+            Some(Hold::Deferred)
+        );
+        assert_eq!(
+            policy.hold(&update(true), Phasing::Unphased, 4 * SECONDS_PER_DAY),
+            None
+        );
+    }
+
+    #[test]
+    fn phased_by_percentage() {
+        let phase = |percentage| Phase {
+            source: "vim",
+            source_version: "2:8.2.3995-1ubuntu2.1",
+            percentage,
+        };
+
+        let config = AptPolicy::default();
+        let policy = on_machine(&config);
+
+        assert_eq!(
+            policy.hold(&update(false), Phasing::Phased(phase(0)), 0),
+            Some(Hold::Phased)
+        );
+        assert_eq!(
+            policy.hold(&update(false), Phasing::Phased(phase(100)), 0),
+            None
+        );
+        assert_eq!(
+            policy.hold(&update(true), Phasing::Phased(phase(0)), 0),
+            None
+        );
+
+        let config = AptPolicy {
+            phased: false,
+            ..AptPolicy::default()
+        };
+
+        assert_eq!(
+            on_machine(&config).hold(&update(false), Phasing::Phased(phase(0)), 0),
+            None
+        );
+
+        // Updates whose phase is unknown are held back, unless phasing is disabled.
+        assert_eq!(
+            policy.hold(&update(false), Phasing::Unknown, 0),
+            Some(Hold::PhaseUnknown)
+        );
+        assert_eq!(policy.hold(&update(true), Phasing::Unknown, 0), None);
+        assert_eq!(
+            on_machine(&config).hold(&update(false), Phasing::Unknown, 0),
+            None
+        );
+
+        // Once a system is in a phase, it remains in it as the phase grows.
+        let machine_id = "b08dfa6083e7567a1921a715000001fb";
+        let first = (0..=100)
+            .position(|percentage| in_phase(machine_id, phase(percentage)))
+            .unwrap();
+
+        assert!((first..=100).all(|percentage| in_phase(machine_id, phase(percentage as u8))));
+    }
+}
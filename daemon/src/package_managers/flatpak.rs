@@ -1,74 +1,192 @@
 // Copyright 2021-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MPL-2.0
 
+use super::PackageManager;
+use crate::progress;
+use crate::runner::Runner;
 use crate::utils;
-use futures::StreamExt;
+use async_trait::async_trait;
+use pop_system_updater::updates::{self, Update};
 use std::path::Path;
 
-pub async fn update(conn: zbus::Connection) {
-    const SOURCE: &str = "flatpak";
+const SOURCE: &str = "flatpak";
 
-    if !utils::command_exists(SOURCE) {
-        return;
+/// Flatpaks of the system installation, or of a user's installation.
+pub struct Flatpak {
+    /// The user whose installation is updated, or `None` for the system's.
+    user: Option<String>,
+    name: String,
+}
+
+impl Flatpak {
+    pub fn system() -> Self {
+        Self {
+            user: None,
+            name: SOURCE.to_owned(),
+        }
+    }
+
+    /// The installation of each desktop user who has logged in before.
+    pub fn users() -> impl Iterator<Item = Self> {
+        crate::accounts::user_names()
+            .filter(|user| {
+                let accounts_service_file = ["/var/lib/AccountsService/users/", user].concat();
+                Path::new(&accounts_service_file).exists()
+            })
+            .map(|user| Self {
+                name: [SOURCE, ":", &user].concat(),
+                user: Some(user),
+            })
     }
 
-    let system = async {
-        info!("{}: updating software for system", SOURCE);
-        let refresh = &[SOURCE, "update", "--noninteractive"];
-        let prune = &[SOURCE, "remove", "--unused", "--noninteractive"];
-        let repair = &[SOURCE, "repair"];
+    /// A flatpak command, which is run as the user if this is their installation.
+    fn command<'a>(&'a self, args: &[&'a str]) -> Vec<&'a str> {
+        let mut command = match self.user {
+            Some(ref user) => vec!["runuser", "-u", user, "--", SOURCE],
+            None => vec![SOURCE],
+        };
 
-        if utils::async_commands(&[refresh, prune]).await.is_err() {
-            if let Err(why) = utils::async_commands(&[repair, refresh, prune]).await {
-                utils::error_handler(&conn, SOURCE, why).await;
-            }
+        command.extend_from_slice(args);
+        command
+    }
+
+    /// The option which selects this installation, which every command is given.
+    fn installation(&self) -> &'static str {
+        if self.user.is_some() {
+            "--user"
+        } else {
+            "--system"
         }
-        info!("{}: updated software for system", SOURCE);
-    };
-
-    let users = async {
-        futures::stream::iter(crate::accounts::user_names())
-            .for_each_concurrent(None, |user| async {
-                let accounts_service_file = ["/var/lib/AccountsService/users/", &user].concat();
-                if !Path::new(&accounts_service_file).exists() {
-                    return;
-                }
-
-                let user = user;
-                info!("{}: updating software for {}", SOURCE, user);
-                let refresh = &[
-                    "runuser",
-                    "-u",
-                    &user,
-                    "--",
-                    SOURCE,
-                    "update",
-                    "--noninteractive",
-                ];
-
-                let prune = &[
-                    "runuser",
-                    "-u",
-                    &user,
-                    "--",
-                    SOURCE,
-                    "remove",
-                    "--unused",
-                    "--noninteractive",
-                ];
-
-                let repair = &["runuser", "-u", &user, "--", SOURCE, "repair", "--user"];
-
-                if utils::async_commands(&[refresh, prune]).await.is_err() {
-                    if let Err(why) = utils::async_commands(&[repair, refresh, prune]).await {
-                        utils::error_handler(&conn, SOURCE, why).await;
-                    }
-                }
-
-                info!("{}: updated software for {}", SOURCE, user);
+    }
+}
+
+#[async_trait]
+impl PackageManager for Flatpak {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn is_available(&self) -> bool {
+        utils::command_exists(SOURCE)
+    }
+
+    async fn pending(&self, runner: &Runner) -> anyhow::Result<Vec<Update>> {
+        let installation = self.installation();
+        let installed =
+            self.command(&["list", installation, "--columns=application,branch,version"]);
+        let updates = self.command(&[
+            "remote-ls",
+            installation,
+            "--updates",
+            "--columns=application,branch,version,download-size",
+        ]);
+
+        let (installed, updates) = futures::try_join!(
+            runner.output(&self.name, &installed),
+            runner.output(&self.name, &updates)
+        )?;
+
+        Ok(parse_updates(
+            &String::from_utf8_lossy(&installed.stdout),
+            &String::from_utf8_lossy(&updates.stdout),
+        ))
+    }
+
+    /// Pulls updates while reporting their progress, without deploying them.
+    async fn download(&self, runner: &Runner) -> anyhow::Result<Vec<String>> {
+        let pull = self.command(&[
+            "update",
+            self.installation(),
+            "--no-deploy",
+            "--noninteractive",
+        ]);
+
+        runner
+            .command_with_progress(&self.name, &pull, Some(progress::flatpak_status))
+            .await?;
+
+        Ok(Vec::new())
+    }
+
+    fn downloads_ahead(&self) -> bool {
+        true
+    }
+
+    /// Deploys the updates which were pulled, then removes unused runtimes.
+    async fn apply(&self, runner: &Runner, _held: &[String]) -> anyhow::Result<()> {
+        let installation = self.installation();
+        let deploy = self.command(&["update", installation, "--no-pull", "--noninteractive"]);
+        let prune = self.command(&["remove", installation, "--unused", "--noninteractive"]);
+
+        runner.command(&self.name, &deploy).await?;
+        runner.command(&self.name, &prune).await
+    }
+
+    async fn repair(&self, runner: &Runner) -> anyhow::Result<()> {
+        let repair = self.command(&["repair", self.installation()]);
+
+        runner.command(&self.name, &repair).await
+    }
+}
+
+/// Pairs the refs which have updates with the versions which are installed.
+///
+/// Both are tab-separated columns, as flatpak writes them when its output is not a terminal.
+fn parse_updates(installed: &str, available: &str) -> Vec<Update> {
+    let installed: Vec<Vec<&str>> = installed.lines().map(columns).collect();
+
+    available
+        .lines()
+        .filter_map(|line| {
+            let mut fields = columns(line).into_iter();
+            let (application, branch) = (fields.next()?, fields.next().unwrap_or(""));
+            let candidate_version = fields.next().unwrap_or("");
+            let download_size = fields.next().and_then(updates::parse_size).unwrap_or(0);
+
+            if application.is_empty() {
+                return None;
+            }
+
+            let current_version = installed
+                .iter()
+                .find(|ref_| ref_.first() == Some(&application) && ref_.get(1) == Some(&branch))
+                .and_then(|ref_| ref_.get(2))
+                .copied()
+                .unwrap_or("");
+
+            Some(Update {
+                name: application.to_owned(),
+                current_version: current_version.to_owned(),
+                candidate_version: candidate_version.to_owned(),
+                download_size,
+                security: false,
             })
-            .await;
-    };
+        })
+        .collect()
+}
 
-    futures::join!(system, users);
+fn columns(line: &str) -> Vec<&str> {
+    line.split('\t').map(str::trim).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_of_installed_refs() {
+        let installed = "org.gnome.Platform\t42\t42.5\norg.mozilla.firefox\tstable\t106.0.5\n";
+        let updates = "org.mozilla.firefox\tstable\t107.0\t86.1 MB\norg.freedesktop.Platform.GL.default\t22.08\t\t1.2 kB\n";
+
+        let updates = parse_updates(installed, updates);
+
+        assert_eq!(updates.len(), 2);
+        assert_eq!(updates[0].name, "org.mozilla.firefox");
+        assert_eq!(updates[0].current_version, "106.0.5");
+        assert_eq!(updates[0].candidate_version, "107.0");
+        assert_eq!(updates[0].download_size, 86_100_000);
+        assert!(updates[1].current_version.is_empty());
+        assert!(updates[1].candidate_version.is_empty());
+        assert_eq!(updates[1].download_size, 1_200);
+    }
 }
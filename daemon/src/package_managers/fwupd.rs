@@ -0,0 +1,153 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+use super::PackageManager;
+use crate::runner::Runner;
+use crate::utils;
+use async_trait::async_trait;
+use pop_system_updater::error::CommandFailed;
+use pop_system_updater::updates::Update;
+use serde::Deserialize;
+
+const SOURCE: &str = "fwupdmgr";
+
+/// The status that `fwupdmgr` exits with when there is nothing to do.
+const NOTHING_TO_DO: i32 = 2;
+
+/// Firmware from the LVFS.
+pub struct Fwupd;
+
+#[async_trait]
+impl PackageManager for Fwupd {
+    fn name(&self) -> &str {
+        SOURCE
+    }
+
+    fn is_available(&self) -> bool {
+        utils::command_exists(SOURCE)
+    }
+
+    async fn refresh(&self, runner: &Runner) -> anyhow::Result<()> {
+        runner
+            .command(SOURCE, &[SOURCE, "refresh", "--force"])
+            .await
+    }
+
+    async fn pending(&self, runner: &Runner) -> anyhow::Result<Vec<Update>> {
+        let output = match runner
+            .output(SOURCE, &[SOURCE, "get-updates", "--json"])
+            .await
+        {
+            Ok(output) => output,
+            Err(why) => {
+                let status = why
+                    .downcast_ref::<CommandFailed>()
+                    .and_then(|failed| failed.status.code());
+
+                let nothing_to_do = status == Some(NOTHING_TO_DO);
+
+                return if nothing_to_do {
+                    Ok(Vec::new())
+                } else {
+                    Err(why)
+                };
+            }
+        };
+
+        Ok(parse_updates(&output.stdout)?)
+    }
+
+    /// Firmware is only installed when the user asks for it.
+    fn installs_pending(&self) -> bool {
+        false
+    }
+
+    async fn apply(&self, _runner: &Runner, _held: &[String]) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Updates {
+    #[serde(default)]
+    devices: Vec<Device>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Device {
+    name: String,
+    #[serde(default)]
+    version: String,
+    /// Releases that the device may be updated to, from the newest.
+    #[serde(default)]
+    releases: Vec<Release>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Release {
+    version: String,
+    #[serde(default)]
+    size: u64,
+    /// Security issues which the release fixes.
+    #[serde(default)]
+    issues: Vec<String>,
+}
+
+/// The newest release of each device in the output of `fwupdmgr get-updates --json`.
+fn parse_updates(output: &[u8]) -> serde_json::Result<Vec<Update>> {
+    let updates: Updates = serde_json::from_slice(output)?;
+
+    Ok(updates
+        .devices
+        .into_iter()
+        .filter_map(|device| {
+            let release = device.releases.into_iter().next()?;
+
+            Some(Update {
+                name: device.name,
+                current_version: device.version,
+                candidate_version: release.version,
+                download_size: release.size,
+                security: !release.issues.is_empty(),
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_of_devices() {
+        let output = br#"{
+            "Devices": [
+                {
+                    "Name": "System Firmware",
+                    "Version": "2022-07-01",
+                    "Releases": [
+                        {
+                            "Version": "2022-11-01",
+                            "Size": 16777216,
+                            "Issues": ["CVE-2022-1234"]
+                        },
+                        { "Version": "2022-09-01", "Size": 16777216 }
+                    ]
+                },
+                { "Name": "Touchpad", "Version": "1.2" }
+            ]
+        }"#;
+
+        let updates = parse_updates(output).unwrap();
+
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].name, "System Firmware");
+        assert_eq!(updates[0].current_version, "2022-07-01");
+        assert_eq!(updates[0].candidate_version, "2022-11-01");
+        assert_eq!(updates[0].download_size, 16_777_216);
+        assert!(updates[0].security);
+    }
+}
@@ -1,52 +1,405 @@
 // Copyright 2021-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MPL-2.0
 
+//! The package managers that the system is updated with.
+//!
+//! Each package manager implements [`PackageManager`], and those which are
+//! installed and enabled by the config are added to a [`Registry`], which
+//! updates with each of them, either all at once or one at a time.
+//!
+//! Package managers which download ahead may be prefetched in the background,
+//! after which an update only applies what was downloaded, holding back the
+//! same updates as when they were downloaded.
+
 pub mod apt;
+pub mod apt_policy;
 pub mod apt_pop;
 pub mod flatpak;
+pub mod fwupd;
 pub mod nix;
+pub mod snap;
+
+use crate::runner::Runner;
+use crate::utils;
+use async_trait::async_trait;
+use pop_system_updater::config::{Backend, Backends};
+use pop_system_updater::history::Source;
+use pop_system_updater::prefetch::{self, Downloaded, Prefetch};
+use pop_system_updater::updates::{Available, Update};
+
+/// A package manager which updates a part of the system.
+///
+/// An update runs each step in order, and stops at the first that fails. Steps
+/// which a package manager does not support are left as they are by default.
+#[async_trait]
+pub trait PackageManager: Send + Sync {
+    /// The name of the source, which progress, errors, and reports are attributed to.
+    fn name(&self) -> &str;
+
+    /// Checks if the package manager is installed.
+    fn is_available(&self) -> bool;
+
+    /// Refreshes the metadata of the packages which are available.
+    async fn refresh(&self, _runner: &Runner) -> anyhow::Result<()> {
+        Ok(())
+    }
 
-pub mod fwupd {
-    pub async fn update(conn: zbus::Connection) {
-        use crate::utils;
+    /// The updates which are available, as of the last refresh.
+    async fn pending(&self, _runner: &Runner) -> anyhow::Result<Vec<Update>> {
+        Ok(Vec::new())
+    }
+
+    /// Checks if applying updates installs those that were pending.
+    ///
+    /// If not, they are listed as available, but are not reported as upgraded.
+    fn installs_pending(&self) -> bool {
+        true
+    }
 
-        const SOURCE: &str = "fwupdmgr";
+    /// Downloads updates without applying them, and returns those which were held back.
+    async fn download(&self, _runner: &Runner) -> anyhow::Result<Vec<String>> {
+        Ok(Vec::new())
+    }
+
+    /// Checks if updates may be downloaded in the background, ahead of the update.
+    fn downloads_ahead(&self) -> bool {
+        false
+    }
 
-        if !utils::command_exists(SOURCE) {
-            return;
+    /// Applies the updates, from those which were downloaded if the package manager downloads ahead.
+    ///
+    /// The updates which were `held` back by the download are held back again.
+    async fn apply(&self, runner: &Runner, held: &[String]) -> anyhow::Result<()>;
+
+    /// Repairs the package manager after an update failed.
+    async fn repair(&self, _runner: &Runner) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// The package managers that updates are performed with, in the order they were registered.
+#[derive(Default)]
+pub struct Registry {
+    managers: Vec<Box<dyn PackageManager>>,
+    /// Each package manager waits for the one before it to finish.
+    sequential: bool,
+}
+
+impl Registry {
+    /// The package managers installed on the system which are enabled, in the configured order.
+    pub fn system(backends: &Backends) -> Self {
+        let mut registry = Self {
+            sequential: backends.sequential,
+            ..Self::default()
+        };
+
+        for &backend in &backends.enabled {
+            match backend {
+                Backend::Apt => registry.register(apt::Apt::new(backends.apt.clone())),
+                Backend::Flatpak => {
+                    registry.register(flatpak::Flatpak::system());
+
+                    if !backends.flatpak_system_only {
+                        for installation in flatpak::Flatpak::users() {
+                            registry.register(installation);
+                        }
+                    }
+                }
+                Backend::Fwupd => registry.register(fwupd::Fwupd),
+                Backend::Nix => registry.register(nix::Nix),
+                Backend::Snap => registry.register(snap::Snap),
+            }
         }
 
-        if let Err(why) = utils::async_command(&[SOURCE, "refresh", "--force"]).await {
-            utils::error_handler(&conn, SOURCE, why).await;
+        registry
+    }
+
+    /// Adds a package manager after those already registered, if it is installed.
+    pub fn register(&mut self, manager: impl PackageManager + 'static) {
+        if manager.is_available() {
+            self.managers.push(Box::new(manager));
+        } else {
+            info!("{}: not available", manager.name());
         }
     }
-}
 
-pub mod snap {
-    pub async fn update(conn: zbus::Connection) {
-        use crate::utils;
+    pub fn managers(&self) -> impl Iterator<Item = &dyn PackageManager> {
+        self.managers.iter().map(AsRef::as_ref)
+    }
+
+    /// Updates with every package manager, and returns what each of them did.
+    ///
+    /// Updates which were completely downloaded ahead of time are applied without being refreshed.
+    pub async fn update(&self, runner: &Runner) -> Vec<Source> {
+        let prefetch = &prefetch::load().await;
+
+        // Every source counts towards the overall progress from the start, even
+        // those which wait for the others to finish.
+        for manager in self.managers() {
+            runner.progress().start(manager.name()).await;
+        }
 
-        const SOURCE: &str = "snap";
+        if !self.sequential {
+            let updates = self.managers().map(|m| update(m, runner, prefetch));
+            return futures::future::join_all(updates).await;
+        }
 
-        info!("{}: updating software for system", SOURCE);
+        let mut sources = Vec::with_capacity(self.managers.len());
 
-        if !utils::command_exists(SOURCE) {
-            return;
+        for manager in self.managers() {
+            sources.push(update(manager, runner, prefetch).await);
         }
 
-        if let Err(why) = utils::async_command(&[SOURCE, "refresh"]).await {
-            utils::error_handler(&conn, SOURCE, why).await;
+        sources
+    }
+
+    /// Refreshes the metadata of each package manager.
+    ///
+    /// Package managers which could not refresh are skipped.
+    pub async fn refresh(&self, runner: &Runner) {
+        for manager in self.managers() {
+            if let Err(why) = manager.refresh(runner).await {
+                error!("{}: failed to refresh: {:#}", manager.name(), why);
+            }
+        }
+    }
+
+    /// Repairs each package manager, and returns the first error, if any failed.
+    pub async fn repair(&self, runner: &Runner) -> anyhow::Result<()> {
+        let mut result = Ok(());
+
+        for manager in self.managers() {
+            if let Err(why) = manager.repair(runner).await {
+                let name = manager.name();
+                error!("{}: failed to repair: {:#}", name, why);
+
+                if result.is_ok() {
+                    result = Err(why.context(format!("failed to repair {}", name)));
+                }
+            }
         }
 
-        info!("{}: updated software for system", SOURCE);
+        result
     }
+
+    /// Downloads updates with each package manager which downloads ahead, and
+    /// records those which were completely downloaded.
+    pub async fn prefetch(&self, runner: &Runner) {
+        let mut prefetch = Prefetch::default();
+
+        for manager in self.managers().filter(|m| m.downloads_ahead()) {
+            let name = manager.name();
+            info!("{}: downloading updates ahead of time", name);
+
+            let updates = if manager.installs_pending() {
+                match manager.pending(runner).await {
+                    Ok(updates) => updates,
+                    Err(why) => {
+                        error!("{}: failed to list updates: {:#}", name, why);
+                        continue;
+                    }
+                }
+            } else {
+                Vec::new()
+            };
+
+            let held = match manager.download(runner).await {
+                Ok(held) => held,
+                Err(why) => {
+                    error!("{}: failed to download updates: {:#}", name, why);
+                    continue;
+                }
+            };
+
+            prefetch.sources.push(Downloaded {
+                source: name.to_owned(),
+                updates,
+                held,
+            });
+
+            if let Err(why) = prefetch::write(&prefetch).await {
+                error!("failed to record downloaded updates: {}", why);
+            }
+        }
+    }
+
+    /// The updates available from each package manager which has any.
+    ///
+    /// Package managers which could not list their updates are skipped.
+    pub async fn pending(&self, runner: &Runner) -> Vec<Available> {
+        let pending = self.managers().map(|manager| async move {
+            match manager.pending(runner).await {
+                Ok(updates) => Some(Available {
+                    source: manager.name().to_owned(),
+                    updates,
+                    automatic: manager.installs_pending(),
+                }),
+                Err(why) => {
+                    error!("{}: failed to list updates: {:#}", manager.name(), why);
+                    None
+                }
+            }
+        });
+
+        futures::future::join_all(pending)
+            .await
+            .into_iter()
+            .flatten()
+            .filter(|available| !available.updates.is_empty())
+            .collect()
+    }
+}
+
+/// Updates with a package manager, and if it fails, repairs it and tries once more.
+pub async fn update(manager: &dyn PackageManager, runner: &Runner, prefetch: &Prefetch) -> Source {
+    let name = manager.name();
+    let downloaded = prefetch
+        .downloaded(name)
+        .filter(|_| manager.downloads_ahead());
+
+    info!("{}: updating software for system", name);
+    runner.progress().start(name).await;
+
+    let mut source = Source::new(name);
+
+    let mut result = upgrade(manager, runner, &mut source, downloaded).await;
+
+    if let Err(why) = result {
+        error!("{}: repairing after failed update: {:#}", name, why);
+
+        // Downloads are refreshed, in case those from before were the cause.
+        result = match manager.repair(runner).await {
+            Ok(()) => upgrade(manager, runner, &mut source, None).await,
+            Err(why) => Err(why),
+        };
+    }
+
+    if let Err(why) = result {
+        // Nothing is reported as upgraded by an update which did not apply.
+        source.packages.clear();
+        source
+            .errors
+            .push(utils::error_handler(runner.connection(), name, why).await);
+    }
+
+    runner.progress().finish(name).await;
+    info!("{}: updated software for system", name);
+
+    source
 }
 
-pub async fn updates_are_available() -> bool {
-    // TODO: Flatpak
-    if let Ok(packages) = apt::packages_to_fetch().await {
-        return !packages.is_empty();
+/// Refreshes and downloads updates, unless they were already downloaded, then applies them.
+async fn upgrade(
+    manager: &dyn PackageManager,
+    runner: &Runner,
+    source: &mut Source,
+    downloaded: Option<&Downloaded>,
+) -> anyhow::Result<()> {
+    let held = match downloaded {
+        Some(downloaded) => {
+            info!("{}: applying updates which were downloaded", manager.name());
+            source.packages = downloaded.updates.iter().cloned().map(Into::into).collect();
+            downloaded.held.clone()
+        }
+        None => {
+            manager.refresh(runner).await?;
+
+            if manager.installs_pending() {
+                let pending = manager.pending(runner).await?;
+                source.packages = pending.into_iter().map(Into::into).collect();
+            }
+
+            manager.download(runner).await?
+        }
+    };
+
+    manager.apply(runner, &held).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Fake {
+        name: &'static str,
+        available: bool,
+        fails: bool,
+    }
+
+    #[async_trait]
+    impl PackageManager for Fake {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn is_available(&self) -> bool {
+            self.available
+        }
+
+        async fn pending(&self, _runner: &Runner) -> anyhow::Result<Vec<Update>> {
+            Ok(vec![Update {
+                name: "vim".into(),
+                ..Update::default()
+            }])
+        }
+
+        async fn apply(&self, _runner: &Runner, _held: &[String]) -> anyhow::Result<()> {
+            if self.fails {
+                return Err(anyhow::anyhow!("failed to apply"));
+            }
+
+            Ok(())
+        }
+    }
+
+    /// A runner whose signals are sent to a peer which is not listening.
+    async fn runner() -> Runner {
+        let (service, client) = tokio::net::UnixStream::pair().unwrap();
+        let guid = zbus::Guid::generate();
+
+        let service = zbus::ConnectionBuilder::unix_stream(service)
+            .server(&guid)
+            .p2p()
+            .build();
+        let client = zbus::ConnectionBuilder::unix_stream(client).p2p().build();
+
+        let (service, _client) = futures::try_join!(service, client).unwrap();
+        Runner::new(service)
     }
 
-    false
+    #[test]
+    fn registers_available_managers_in_order() {
+        let mut registry = Registry::default();
+
+        for &(name, available) in &[("b", true), ("c", false), ("a", true)] {
+            registry.register(Fake {
+                name,
+                available,
+                fails: false,
+            });
+        }
+
+        let names = registry.managers().map(|m| m.name()).collect::<Vec<_>>();
+        assert_eq!(names, ["b", "a"]);
+    }
+
+    #[tokio::test]
+    async fn failed_updates_report_no_packages() {
+        let runner = runner().await;
+        let prefetch = Prefetch::default();
+
+        let fake = |fails| Fake {
+            name: "fake",
+            available: true,
+            fails,
+        };
+
+        let source = update(&fake(false), &runner, &prefetch).await;
+        assert_eq!(source.packages.len(), 1);
+        assert!(source.errors.is_empty());
+
+        let source = update(&fake(true), &runner, &prefetch).await;
+        assert!(source.packages.is_empty());
+        assert_eq!(source.errors.len(), 1);
+    }
 }
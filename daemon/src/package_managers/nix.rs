@@ -1,24 +1,103 @@
 // Copyright 2021-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MPL-2.0
 
+use super::PackageManager;
+use crate::runner::Runner;
 use crate::utils;
+use async_trait::async_trait;
+use pop_system_updater::updates::Update;
 
-pub async fn update(conn: &zbus::Connection) {
-    const SOURCE: &str = "nix";
+const SOURCE: &str = "nix";
 
-    const COMMANDS: &[&[&str]] = &[
-        &["nix-channel", "--update"],
-        &["nix-env", "--upgrade"],
-        &["nix-collect-garbage", "-d"],
-    ];
+const CHANNEL_UPDATE: &[&str] = &["nix-channel", "--update"];
+const UPGRADE: &[&str] = &["nix-env", "--upgrade"];
+const COLLECT_GARBAGE: &[&str] = &["nix-collect-garbage", "-d"];
 
-    for command in COMMANDS {
-        if !utils::command_exists(command[0]) {
-            return;
-        }
+/// Packages installed with Nix.
+pub struct Nix;
+
+#[async_trait]
+impl PackageManager for Nix {
+    fn name(&self) -> &str {
+        SOURCE
+    }
+
+    fn is_available(&self) -> bool {
+        [CHANNEL_UPDATE, UPGRADE, COLLECT_GARBAGE]
+            .iter()
+            .all(|command| utils::command_exists(command[0]))
     }
 
-    if let Err(why) = utils::async_commands(COMMANDS).await {
-        utils::error_handler(conn, SOURCE, why).await;
+    async fn refresh(&self, runner: &Runner) -> anyhow::Result<()> {
+        runner.command(SOURCE, CHANNEL_UPDATE).await?;
+        runner.progress().report(SOURCE, 33).await;
+        Ok(())
+    }
+
+    async fn pending(&self, runner: &Runner) -> anyhow::Result<Vec<Update>> {
+        let output = runner
+            .output(SOURCE, &["nix-env", "--upgrade", "--dry-run"])
+            .await?;
+
+        // The upgrades are described on stderr.
+        Ok(String::from_utf8_lossy(&output.stderr)
+            .lines()
+            .filter_map(parse_upgrade)
+            .collect())
+    }
+
+    async fn apply(&self, runner: &Runner, _held: &[String]) -> anyhow::Result<()> {
+        runner.command(SOURCE, UPGRADE).await?;
+        runner.progress().report(SOURCE, 66).await;
+        runner.command(SOURCE, COLLECT_GARBAGE).await
+    }
+}
+
+/// Parses an upgrade from the output of `nix-env --upgrade --dry-run`.
+///
+/// ```text
+/// upgrading 'hello-2.10' to 'hello-2.12.1'
+/// ```
+fn parse_upgrade(line: &str) -> Option<Update> {
+    let mut quoted = line.strip_prefix("upgrading ")?.split('\'');
+    let (name, current_version) = split_name(quoted.nth(1)?);
+    let (_, candidate_version) = split_name(quoted.nth(1)?);
+
+    Some(Update {
+        name: name.to_owned(),
+        current_version: current_version.to_owned(),
+        candidate_version: candidate_version.to_owned(),
+        ..Update::default()
+    })
+}
+
+/// Splits a derivation name at the first dash which is not followed by a letter, as nix does.
+fn split_name(derivation: &str) -> (&str, &str) {
+    let version = derivation
+        .match_indices('-')
+        .map(|(position, _)| position)
+        .find(|&position| {
+            matches!(derivation[position + 1..].chars().next(), Some(c) if !c.is_alphabetic())
+        });
+
+    match version {
+        Some(position) => (&derivation[..position], &derivation[position + 1..]),
+        None => (derivation, ""),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn upgrade_lines() {
+        let update = parse_upgrade("upgrading 'gnome-tweaks-42.0' to 'gnome-tweaks-42.1'").unwrap();
+
+        assert_eq!(update.name, "gnome-tweaks");
+        assert_eq!(update.current_version, "42.0");
+        assert_eq!(update.candidate_version, "42.1");
+
+        assert!(parse_upgrade("(dry run; not doing `nix-env --upgrade`)").is_none());
     }
 }
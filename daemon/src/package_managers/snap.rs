@@ -0,0 +1,103 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+use super::PackageManager;
+use crate::runner::Runner;
+use crate::utils;
+use async_trait::async_trait;
+use pop_system_updater::updates::{self, Update};
+
+const SOURCE: &str = "snap";
+
+/// Snaps installed from the Snap Store.
+pub struct Snap;
+
+#[async_trait]
+impl PackageManager for Snap {
+    fn name(&self) -> &str {
+        SOURCE
+    }
+
+    fn is_available(&self) -> bool {
+        utils::command_exists(SOURCE)
+    }
+
+    async fn pending(&self, runner: &Runner) -> anyhow::Result<Vec<Update>> {
+        let (installed, updates) = futures::try_join!(
+            runner.output(SOURCE, &[SOURCE, "list"]),
+            runner.output(SOURCE, &[SOURCE, "refresh", "--list"])
+        )?;
+
+        Ok(parse_updates(
+            &String::from_utf8_lossy(&installed.stdout),
+            &String::from_utf8_lossy(&updates.stdout),
+        ))
+    }
+
+    async fn apply(&self, runner: &Runner, _held: &[String]) -> anyhow::Result<()> {
+        runner.command(SOURCE, &[SOURCE, "refresh"]).await
+    }
+}
+
+/// Pairs the snaps in `snap refresh --list` with their versions in `snap list`.
+///
+/// ```text
+/// Name     Version  Rev   Size   Publisher  Notes
+/// firefox  107.0-2  2154  170MB  mozilla**  -
+/// ```
+fn parse_updates(installed: &str, available: &str) -> Vec<Update> {
+    available
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let name = fields.next()?;
+            let candidate_version = fields.next()?;
+            let download_size = fields.nth(1).and_then(updates::parse_size).unwrap_or(0);
+
+            let current_version = installed
+                .lines()
+                .skip(1)
+                .map(|line| line.split_whitespace())
+                .find_map(|mut fields| {
+                    if fields.next() == Some(name) {
+                        fields.next()
+                    } else {
+                        None
+                    }
+                })
+                .unwrap_or("");
+
+            Some(Update {
+                name: name.to_owned(),
+                current_version: current_version.to_owned(),
+                candidate_version: candidate_version.to_owned(),
+                download_size,
+                security: false,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_of_installed_snaps() {
+        let installed = "Name     Version   Rev   Tracking       Publisher   Notes\n\
+                         core20   20221027  1695  latest/stable  canonical**  base\n\
+                         firefox  106.0.5-1 2088  latest/stable  mozilla**    -\n";
+        let updates = "Name     Version  Rev   Size   Publisher  Notes\n\
+                       firefox  107.0-2  2154  170MB  mozilla**  -\n";
+
+        let updates = parse_updates(installed, updates);
+
+        assert_eq!(updates.len(), 1);
+        assert_eq!(updates[0].name, "firefox");
+        assert_eq!(updates[0].current_version, "106.0.5-1");
+        assert_eq!(updates[0].candidate_version, "107.0-2");
+        assert_eq!(updates[0].download_size, 170_000_000);
+        assert!(parse_updates(installed, "").is_empty());
+    }
+}
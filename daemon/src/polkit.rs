@@ -0,0 +1,157 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! Authorizes the callers of the system service's methods with polkit.
+//!
+//! Each method which changes the system checks its own action, which are
+//! declared in `data/com.system76.SystemUpdater.policy`. Methods which only
+//! query the state of the service are open to every caller.
+
+use enumflags2::{bitflags, BitFlags};
+use std::collections::HashMap;
+use zbus::names::UniqueName;
+use zvariant::Value;
+
+/// Lets the agent of the caller prompt them for authentication.
+const ALLOW_USER_INTERACTION: u32 = 1;
+
+/// The polkit actions that callers may be authorized for.
+#[bitflags]
+#[repr(u8)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Action {
+    CheckForUpdates = 1,
+    Defer = 1 << 1,
+    ReadLog = 1 << 2,
+    Repair = 1 << 3,
+    SetAutoUpdate = 1 << 4,
+    SetBackends = 1 << 5,
+    SetSchedule = 1 << 6,
+    Update = 1 << 7,
+}
+
+impl Action {
+    /// The ID of the action in the policy file.
+    #[must_use]
+    pub fn id(self) -> &'static str {
+        match self {
+            Action::CheckForUpdates => "com.system76.SystemUpdater.check-for-updates",
+            Action::Defer => "com.system76.SystemUpdater.defer",
+            Action::ReadLog => "com.system76.SystemUpdater.read-log",
+            Action::Repair => "com.system76.SystemUpdater.repair",
+            Action::SetAutoUpdate => "com.system76.SystemUpdater.set-auto-update",
+            Action::SetBackends => "com.system76.SystemUpdater.set-backends",
+            Action::SetSchedule => "com.system76.SystemUpdater.set-schedule",
+            Action::Update => "com.system76.SystemUpdater.update",
+        }
+    }
+}
+
+#[dbus_proxy(
+    interface = "org.freedesktop.PolicyKit1.Authority",
+    default_service = "org.freedesktop.PolicyKit1",
+    default_path = "/org/freedesktop/PolicyKit1/Authority"
+)]
+trait Authority {
+    fn check_authorization(
+        &self,
+        subject: &(&str, HashMap<&str, Value<'_>>),
+        action_id: &str,
+        details: HashMap<&str, &str>,
+        flags: u32,
+        cancellation_id: &str,
+    ) -> zbus::Result<(bool, bool, HashMap<String, String>)>;
+}
+
+/// Decides which actions the callers of the service are authorized to perform.
+pub enum Authority {
+    /// Asks polkit on the system bus.
+    Polkit(zbus::Connection),
+
+    /// Grants the same actions to every caller, without asking polkit.
+    ///
+    /// Intended for tests, and for running the service without polkit.
+    Local(BitFlags<Action>),
+}
+
+impl Authority {
+    /// Checks if the caller which sent a message is authorized to perform an action.
+    ///
+    /// # Errors
+    ///
+    /// Returns `AccessDenied` if the caller is not authorized, or if polkit could not be reached.
+    pub async fn check(
+        &self,
+        sender: Option<&UniqueName<'_>>,
+        action: Action,
+    ) -> zbus::fdo::Result<()> {
+        let authorized = match self {
+            Authority::Local(actions) => actions.contains(action),
+            Authority::Polkit(connection) => match sender {
+                Some(sender) => polkit_check(connection, sender, action).await?,
+                None => false,
+            },
+        };
+
+        if authorized {
+            Ok(())
+        } else {
+            info!("denied {} to {:?}", action.id(), sender);
+            Err(zbus::fdo::Error::AccessDenied(format!(
+                "not authorized for {}",
+                action.id()
+            )))
+        }
+    }
+}
+
+async fn polkit_check(
+    connection: &zbus::Connection,
+    sender: &UniqueName<'_>,
+    action: Action,
+) -> zbus::fdo::Result<bool> {
+    let failed = |why: zbus::Error| {
+        error!("failed to check authorization with polkit: {}", why);
+        zbus::fdo::Error::AccessDenied(format!("could not check authorization: {}", why))
+    };
+
+    let proxy = AuthorityProxy::new(connection).await.map_err(failed)?;
+
+    let mut subject = HashMap::new();
+    subject.insert("name", Value::from(sender.as_str()));
+
+    let (authorized, _challenge, _details) = proxy
+        .check_authorization(
+            &("system-bus-name", subject),
+            action.id(),
+            HashMap::new(),
+            ALLOW_USER_INTERACTION,
+            "",
+        )
+        .await
+        .map_err(failed)?;
+
+    Ok(authorized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::convert::TryFrom;
+
+    #[tokio::test]
+    async fn local_authority() {
+        let sender = UniqueName::try_from(":1.42").unwrap();
+        let authority = Authority::Local(Action::CheckForUpdates | Action::Defer);
+
+        assert!(authority
+            .check(Some(&sender), Action::CheckForUpdates)
+            .await
+            .is_ok());
+        assert!(authority.check(None, Action::Defer).await.is_ok());
+        assert!(matches!(
+            authority.check(Some(&sender), Action::Update).await,
+            Err(zbus::fdo::Error::AccessDenied(_))
+        ));
+    }
+}
@@ -0,0 +1,94 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! Records which updates were downloaded ahead of the scheduled update.
+//!
+//! After a check for updates finds some, they are downloaded in the background.
+//! A source is only recorded once every one of its updates was downloaded, so
+//! that an update never begins installing from a partial download. The record
+//! is cleared whenever package lists are refreshed, or updates are installed.
+//!
+//! The updates which were downloaded, and those which were held back, are kept
+//! with each source, so that exactly those are installed later.
+
+use crate::config::{self, Versioned};
+use crate::updates::Update;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::Path;
+
+pub const PREFETCH_PATH: &str = "/var/cache/pop-system-updater/prefetch.ron";
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Prefetch {
+    /// The version of the format that the file was written in.
+    pub version: u32,
+    /// Sources whose updates were completely downloaded.
+    pub sources: Vec<Downloaded>,
+}
+
+/// The updates of a source which were completely downloaded.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct Downloaded {
+    pub source: String,
+    /// The updates which were downloaded, if the source lists them.
+    pub updates: Vec<Update>,
+    /// Updates which were held back, which are held back again when installing.
+    pub held: Vec<String>,
+}
+
+impl Default for Prefetch {
+    fn default() -> Self {
+        Self {
+            version: Self::VERSION,
+            sources: Vec::new(),
+        }
+    }
+}
+
+impl Versioned for Prefetch {
+    /// 1. Sources which were completely downloaded.
+    /// 2. With the updates that were downloaded, and those held back.
+    const VERSION: u32 = 2;
+
+    fn migrate(version: Option<u32>, _file: &str) -> Option<Self> {
+        // What was held back was not recorded, so those updates are downloaded again.
+        match version {
+            Some(1) => Some(Self::default()),
+            _ => None,
+        }
+    }
+}
+
+impl Prefetch {
+    /// The updates of a source, if they were completely downloaded.
+    #[must_use]
+    pub fn downloaded(&self, source: &str) -> Option<&Downloaded> {
+        self.sources
+            .iter()
+            .find(|downloaded| downloaded.source == source)
+    }
+}
+
+/// The sources which were downloaded, or none if nothing was recorded.
+pub async fn load() -> Prefetch {
+    config::read_system_file(Path::new(PREFETCH_PATH))
+        .await
+        .unwrap_or_default()
+}
+
+/// # Errors
+///
+/// Errors if the record could not be written.
+pub async fn write(prefetch: &Prefetch) -> io::Result<()> {
+    config::write_system_file(Path::new(PREFETCH_PATH), prefetch).await
+}
+
+/// Forgets which sources were downloaded, as their downloads may no longer be complete.
+pub async fn clear() {
+    if let Err(why) = tokio::fs::remove_file(PREFETCH_PATH).await {
+        if why.kind() != io::ErrorKind::NotFound {
+            error!("failed to clear downloaded updates: {}", why);
+        }
+    }
+}
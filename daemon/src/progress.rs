@@ -0,0 +1,195 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! Aggregates the progress reported by each package manager during an update.
+//!
+//! Each source emits its own `Progress` signal as its progress changes, along
+//! with the overall progress of the update under the [`OVERALL`] source, which
+//! is the average progress of the sources that have started. Every source of an
+//! update is started at once, so that those which run one at a time count
+//! towards the overall progress before they run.
+
+use pop_system_updater::dbus::server::{context, Server};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex};
+
+/// The source of the signals for the overall progress of the update.
+pub const OVERALL: &str = "all";
+
+/// Tracks the progress of each source of an update.
+#[derive(Clone)]
+pub struct Progress {
+    connection: zbus::Connection,
+    state: Arc<Mutex<State>>,
+    /// If progress is signaled, which it is only for updates.
+    signaled: bool,
+}
+
+#[derive(Default)]
+struct State {
+    sources: BTreeMap<String, u8>,
+    overall: u8,
+}
+
+impl Progress {
+    pub fn new(connection: zbus::Connection) -> Self {
+        Self {
+            connection,
+            state: Arc::default(),
+            signaled: true,
+        }
+    }
+
+    /// Progress which is not signaled, for work done in the background of the update.
+    pub fn hidden(connection: zbus::Connection) -> Self {
+        Self {
+            signaled: false,
+            ..Self::new(connection)
+        }
+    }
+
+    /// Counts a source towards the overall progress, starting at 0%.
+    pub async fn start(&self, source: &str) {
+        self.report(source, 0).await;
+    }
+
+    /// Marks a source as complete, whether or not it succeeded.
+    pub async fn finish(&self, source: &str) {
+        self.report(source, 100).await;
+    }
+
+    /// Changes the progress of a source, and signals it if it changed.
+    pub async fn report(&self, source: &str, percent: u8) {
+        if !self.signaled {
+            return;
+        }
+
+        let percent = percent.min(100);
+
+        let overall = {
+            let mut state = self.state.lock().unwrap();
+
+            if state.sources.get(source) == Some(&percent) {
+                return;
+            }
+
+            state.sources.insert(source.to_owned(), percent);
+
+            let total: usize = state.sources.values().map(|&p| usize::from(p)).sum();
+            let average = (total / state.sources.len()) as u8;
+
+            // Sources which start late would otherwise move the progress bar backwards.
+            if average > state.overall {
+                state.overall = average;
+                Some(average)
+            } else {
+                None
+            }
+        };
+
+        context(&self.connection, |ctx| async move {
+            Server::progress(&ctx, source, percent).await?;
+
+            if let Some(overall) = overall {
+                Server::progress(&ctx, OVERALL, overall).await?;
+            }
+
+            Ok(())
+        })
+        .await;
+    }
+}
+
+/// Parses a line of apt's `APT::Status-Fd` output.
+///
+/// Downloads are the first half of the progress, and the installation is the second.
+///
+/// ```text
+/// dlstatus:1:9.2:Retrieving file 1 of 10
+/// pmstatus:libc6:amd64:20.5:Preparing to configure libc6:amd64
+/// ```
+pub fn apt_status(line: &str) -> Option<u8> {
+    let mut fields = line.split(':');
+
+    let (offset, percent) = match fields.next()? {
+        "dlstatus" => (0.0, fields.nth(1)),
+        "pmstatus" => {
+            // The name of the package may be followed by its architecture.
+            let _package = fields.next()?;
+            let field = fields.next()?;
+            if field.parse::<f32>().is_ok() {
+                (50.0, Some(field))
+            } else {
+                (50.0, fields.next())
+            }
+        }
+        _ => return None,
+    };
+
+    let percent = percent?.parse::<f32>().ok()?.clamp(0.0, 100.0);
+
+    Some((offset + percent / 2.0) as u8)
+}
+
+/// Parses a line of `flatpak update` output, such as `Updating 2/4… ████  50%  1.2 MB/s`.
+///
+/// Each ref that is updated is an equal share of the progress.
+pub fn flatpak_status(line: &str) -> Option<u8> {
+    let mut position = None;
+    let mut percent = 0;
+
+    for word in line.split_whitespace() {
+        if let Some(value) = word.strip_suffix('%') {
+            if let Ok(value) = value.parse::<u8>() {
+                percent = value.min(100);
+            }
+        } else if let Some((current, total)) = word.trim_end_matches(['…', '.']).split_once('/') {
+            if let (Ok(current), Ok(total)) = (current.parse::<u32>(), total.parse::<u32>()) {
+                position = Some((current, total));
+            }
+        }
+    }
+
+    match position? {
+        (current, total) if current > 0 && current <= total => {
+            let done = f64::from(current - 1) + f64::from(percent) / 100.0;
+            Some((done * 100.0 / f64::from(total)) as u8)
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apt_status_lines() {
+        assert_eq!(apt_status("dlstatus:1:0:Retrieving file 1 of 4"), Some(0));
+        assert_eq!(
+            apt_status("dlstatus:3:50.5:Retrieving file 3 of 4"),
+            Some(25)
+        );
+        assert_eq!(apt_status("pmstatus:dpkg-exec:0:Running dpkg"), Some(50));
+        assert_eq!(
+            apt_status("pmstatus:libc6:amd64:60:Preparing to configure libc6:amd64"),
+            Some(80)
+        );
+        assert_eq!(apt_status("pmstatus:vim:100:Installed vim"), Some(100));
+        assert_eq!(apt_status("pmconffile:/etc/foo:/etc/foo.dpkg-new:1"), None);
+        assert_eq!(apt_status("Reading package lists..."), None);
+    }
+
+    #[test]
+    fn flatpak_status_lines() {
+        assert_eq!(flatpak_status("Updating 1/4… ████  0%  1.2 MB/s"), Some(0));
+        assert_eq!(
+            flatpak_status("Updating 2/4… ████  50%  1.2 MB/s"),
+            Some(37)
+        );
+        assert_eq!(flatpak_status("Updating 4/4... 100%"), Some(100));
+        assert_eq!(flatpak_status("Updating 3/4"), Some(50));
+        assert_eq!(flatpak_status("Looking for updates…"), None);
+        assert_eq!(flatpak_status("Updating 5/4… 10%"), None);
+    }
+}
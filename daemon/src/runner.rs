@@ -0,0 +1,418 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! Runs the commands of the package managers, and captures their output.
+//!
+//! Each line that a command writes is logged, emitted in a `Log` signal, and if
+//! the command was run by an update, appended to the log of that update in the
+//! history. The last lines are kept, and attached to the error if it fails.
+//!
+//! Lines longer than [`MAX_LINE`] bytes are split, and no more than [`MAX_LOG`]
+//! bytes are stored for each update.
+//!
+//! Commands which only query the package managers are run with [`Runner::output`],
+//! whose output is returned to the caller instead, and which fail if they write
+//! more than [`MAX_LOG`] bytes to either output.
+
+use crate::progress::Progress;
+use anyhow::Context;
+use pop_system_updater::dbus::server::{context, Server};
+use pop_system_updater::error::{CommandFailed, OUTPUT_LINES};
+use pop_system_updater::history;
+use std::collections::VecDeque;
+use std::fs::Permissions;
+use std::io;
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use std::process::{Output, Stdio};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+
+/// The most bytes of a line which are handled at once.
+pub const MAX_LINE: usize = 4096;
+
+/// The most bytes of output which are stored for each update.
+pub const MAX_LOG: u64 = 8 * 1024 * 1024;
+
+/// Which output of a command that a line was written to.
+#[derive(Clone, Copy)]
+enum Stream {
+    Stdout,
+    Stderr,
+}
+
+impl Stream {
+    fn as_str(self) -> &'static str {
+        match self {
+            Stream::Stdout => "stdout",
+            Stream::Stderr => "stderr",
+        }
+    }
+}
+
+/// Reports progress from lines of output which contain it.
+type Parser = fn(&str) -> Option<u8>;
+
+/// Runs commands on behalf of the package managers.
+#[derive(Clone)]
+pub struct Runner {
+    connection: zbus::Connection,
+    progress: Progress,
+    log: Option<Arc<tokio::sync::Mutex<Log>>>,
+}
+
+impl Runner {
+    /// A runner whose output is logged and signaled, but not stored.
+    pub fn new(connection: zbus::Connection) -> Self {
+        Self {
+            progress: Progress::new(connection.clone()),
+            connection,
+            log: None,
+        }
+    }
+
+    /// A runner whose progress is not signaled, as it does not run an update.
+    pub fn background(connection: zbus::Connection) -> Self {
+        Self {
+            progress: Progress::hidden(connection.clone()),
+            ..Self::new(connection)
+        }
+    }
+
+    /// A runner which also stores output in the log of the update that started at the given time.
+    pub async fn for_update(connection: zbus::Connection, started: u64) -> Self {
+        let mut runner = Self::new(connection);
+
+        match Log::create(started).await {
+            Ok(log) => runner.log = Some(Arc::new(tokio::sync::Mutex::new(log))),
+            Err(why) => error!("failed to create update log: {}", why),
+        }
+
+        runner
+    }
+
+    pub fn connection(&self) -> &zbus::Connection {
+        &self.connection
+    }
+
+    /// The progress of the update that the commands are run by.
+    pub fn progress(&self) -> &Progress {
+        &self.progress
+    }
+
+    /// Runs a command, whose first argument is the program.
+    pub async fn command(&self, source: &str, args: &[&str]) -> anyhow::Result<()> {
+        self.command_with_progress(source, args, None).await
+    }
+
+    /// Runs a command, and reports the progress parsed from its output to the source's progress.
+    pub async fn command_with_progress(
+        &self,
+        source: &str,
+        args: &[&str],
+        parse: Option<Parser>,
+    ) -> anyhow::Result<()> {
+        let (program, args_) = args
+            .split_first()
+            .context("command was invoked without arguments")?;
+
+        let mut command = Command::new(program);
+        command.args(args_);
+
+        self.run(source, &mut command, parse)
+            .await
+            .with_context(|| format!("command execution failed for {:?}", args))
+    }
+
+    /// Runs a command which queries a package manager, and returns what it wrote.
+    pub async fn output(&self, source: &str, args: &[&str]) -> anyhow::Result<Output> {
+        let (program, args_) = args
+            .split_first()
+            .context("command was invoked without arguments")?;
+
+        let mut command = Command::new(program);
+        command.args(args_);
+
+        let description = describe(&command);
+        info!("{}: running `{}`", source, description);
+
+        let mut child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .kill_on_drop(true)
+            .spawn()
+            .with_context(|| format!("command execution failed for {:?}", args))?;
+
+        let stdout = child.stdout.take().context("stdout was not captured")?;
+        let stderr = child.stderr.take().context("stderr was not captured")?;
+
+        // Both outputs are read at once, so that neither pipe fills up and blocks the command.
+        let (stdout, stderr) = futures::try_join!(read_to_limit(stdout), read_to_limit(stderr))
+            .with_context(|| format!("command execution failed for {:?}", args))?;
+
+        let output = Output {
+            status: child.wait().await?,
+            stdout,
+            stderr,
+        };
+
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            return Err(CommandFailed::new(description, output.status, &stderr).into());
+        }
+
+        Ok(output)
+    }
+
+    /// Runs a command which was already configured.
+    ///
+    /// Lines of output which the parser reads progress from are reported as
+    /// progress instead of being logged.
+    pub async fn run(
+        &self,
+        source: &str,
+        command: &mut Command,
+        parse: Option<Parser>,
+    ) -> anyhow::Result<()> {
+        let description = describe(command);
+        info!("{}: running `{}`", source, description);
+
+        if let Some(ref log) = self.log {
+            log.lock()
+                .await
+                .write(&format!("{} $ {}\n", source, description))
+                .await;
+        }
+
+        let mut child = command
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let stdout = child.stdout.take().context("stdout was not captured")?;
+        let stderr = child.stderr.take().context("stderr was not captured")?;
+
+        // Both outputs are read at once, so that neither pipe fills up and blocks the command.
+        let tail = Mutex::new(VecDeque::with_capacity(OUTPUT_LINES));
+        let (stdout, stderr) = futures::join!(
+            self.read(source, Stream::Stdout, stdout, parse, &tail),
+            self.read(source, Stream::Stderr, stderr, None, &tail)
+        );
+
+        stdout?;
+        stderr?;
+
+        let status = child.wait().await?;
+        if !status.success() {
+            let output = Vec::from(tail.into_inner().unwrap()).join("\n");
+            return Err(CommandFailed::new(description, status, &output).into());
+        }
+
+        Ok(())
+    }
+
+    async fn read(
+        &self,
+        source: &str,
+        stream: Stream,
+        mut reader: impl AsyncRead + Unpin,
+        parse: Option<Parser>,
+        tail: &Mutex<VecDeque<String>>,
+    ) -> io::Result<()> {
+        let mut buffer = [0u8; 4096];
+        let mut pending = Vec::new();
+
+        loop {
+            let read = reader.read(&mut buffer).await?;
+            if read == 0 {
+                break;
+            }
+
+            pending.extend_from_slice(&buffer[..read]);
+
+            // Carriage returns are used to redraw progress bars.
+            while let Some(end) = pending.iter().position(|&b| b == b'\n' || b == b'\r') {
+                let line = pending.drain(..=end).collect::<Vec<u8>>();
+                self.line(source, stream, &line, parse, tail).await;
+            }
+
+            if pending.len() >= MAX_LINE {
+                let line = std::mem::take(&mut pending);
+                self.line(source, stream, &line, parse, tail).await;
+            }
+        }
+
+        self.line(source, stream, &pending, parse, tail).await;
+
+        Ok(())
+    }
+
+    async fn line(
+        &self,
+        source: &str,
+        stream: Stream,
+        line: &[u8],
+        parse: Option<Parser>,
+        tail: &Mutex<VecDeque<String>>,
+    ) {
+        let line = String::from_utf8_lossy(line);
+        let line = line.trim_end();
+
+        if line.is_empty() {
+            return;
+        }
+
+        if let Some(percent) = parse.and_then(|parse| parse(line)) {
+            self.progress.report(source, percent).await;
+            return;
+        }
+
+        info!("{}: {}", source, line);
+
+        {
+            let mut tail = tail.lock().unwrap();
+            if tail.len() == OUTPUT_LINES {
+                tail.pop_front();
+            }
+
+            tail.push_back(line.to_owned());
+        }
+
+        if let Some(ref log) = self.log {
+            log.lock()
+                .await
+                .write(&format!("{} {}: {}\n", source, stream.as_str(), line))
+                .await;
+        }
+
+        context(&self.connection, |ctx| async move {
+            Server::log(&ctx, source, stream.as_str(), line).await
+        })
+        .await;
+    }
+}
+
+/// Reads the whole of an output, unless it is longer than [`MAX_LOG`].
+async fn read_to_limit(reader: impl AsyncRead + Unpin) -> io::Result<Vec<u8>> {
+    let mut output = Vec::new();
+    reader.take(MAX_LOG + 1).read_to_end(&mut output).await?;
+
+    if output.len() as u64 > MAX_LOG {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("output exceeded {} bytes", MAX_LOG),
+        ));
+    }
+
+    Ok(output)
+}
+
+/// The log of an update, which stops being written to once it reaches [`MAX_LOG`].
+struct Log {
+    file: Option<tokio::fs::File>,
+    written: u64,
+}
+
+impl Log {
+    async fn create(started: u64) -> io::Result<Self> {
+        Self::create_in(Path::new(history::HISTORY_DIR), &history::log_path(started)).await
+    }
+
+    /// Creates a log which only root may read, as `UpdateLog` requires authorization.
+    async fn create_in(dir: &Path, path: &Path) -> io::Result<Self> {
+        tokio::fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .await?;
+
+        // Older releases created the directory with the default mode.
+        tokio::fs::set_permissions(dir, Permissions::from_mode(0o700)).await?;
+
+        let file = tokio::fs::OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .mode(0o600)
+            .open(path)
+            .await?;
+
+        Ok(Self {
+            file: Some(file),
+            written: 0,
+        })
+    }
+
+    async fn write(&mut self, entry: &str) {
+        let file = match self.file.as_mut() {
+            Some(file) => file,
+            None => return,
+        };
+
+        self.written += entry.len() as u64;
+        let truncated = self.written > MAX_LOG;
+
+        let entry = if truncated { "log truncated\n" } else { entry };
+
+        let mut result = file.write_all(entry.as_bytes()).await;
+        if result.is_ok() {
+            result = file.flush().await;
+        }
+
+        if let Err(ref why) = result {
+            error!("failed to write update log: {}", why);
+        }
+
+        if truncated || result.is_err() {
+            self.file = None;
+        }
+    }
+}
+
+/// The program and arguments of a command.
+fn describe(command: &Command) -> String {
+    let command = command.as_std();
+
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn outputs_are_limited() {
+        let output = vec![b'a'; MAX_LOG as usize];
+        assert_eq!(
+            read_to_limit(&output[..]).await.unwrap().len(),
+            output.len()
+        );
+
+        let output = vec![b'a'; MAX_LOG as usize + 1];
+        assert!(read_to_limit(&output[..]).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn logs_are_only_readable_by_root() {
+        let dir = std::env::temp_dir()
+            .join(format!("pop-system-updater-logs-{}", std::process::id()))
+            .join("history");
+
+        let path = dir.join("1.log");
+        let log = Log::create_in(&dir, &path).await;
+
+        let dir_mode = std::fs::metadata(&dir).map(|m| m.permissions().mode() & 0o777);
+        let log_mode = std::fs::metadata(&path).map(|m| m.permissions().mode() & 0o777);
+        std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+
+        assert!(log.is_ok());
+        assert_eq!(dir_mode.unwrap(), 0o700);
+        assert_eq!(log_mode.unwrap(), 0o600);
+    }
+}
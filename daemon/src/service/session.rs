@@ -2,15 +2,16 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use anyhow::Context;
+use async_cron_scheduler::{Job, JobId, Scheduler};
 use config::{Frequency, Local, LocalCache};
 use flume::Sender;
+use futures::StreamExt;
 use pop_system_updater::config;
 use pop_system_updater::dbus::PopService;
 use pop_system_updater::dbus::{
     client::ClientProxy, local_server::LocalServer, LocalEvent, IFACE_LOCAL,
 };
 use std::time::{Duration, SystemTime};
-use tokio::task::JoinHandle;
 use zbus::Connection;
 
 pub async fn run() -> anyhow::Result<()> {
@@ -24,7 +25,7 @@ pub async fn run() -> anyhow::Result<()> {
         .await
         .context("could not initiate connection to service")?;
 
-    let _system_proxy = ClientProxy::new(&system_connection)
+    let system_proxy = ClientProxy::new(&system_connection)
         .await
         .context("could not get proxy from connection")?;
 
@@ -57,12 +58,15 @@ pub async fn run() -> anyhow::Result<()> {
             other => anyhow::anyhow!("could not register user service: {}", other),
         })?;
 
+    let (scheduler, scheduler_service) = Scheduler::<chrono::Local>::launch(tokio::time::sleep);
+    tokio::spawn(scheduler_service);
+    tokio::spawn(system_config_changes(system_proxy.clone(), sender.clone()));
+
     let mut state = State {
+        system_proxy,
         cache: config::load_session_cache().await,
-        schedule_handle: tokio::spawn(update_on(
-            sender.clone(),
-            Duration::from_secs(SECONDS_IN_DAY),
-        )),
+        scheduler,
+        check_job: None,
         sender,
     };
 
@@ -71,6 +75,28 @@ pub async fn run() -> anyhow::Result<()> {
     while let Ok(event) = receiver.recv_async().await {
         match event {
             LocalEvent::CheckUpdates => state.check_for_updates(&config).await,
+            LocalEvent::SystemConfigChanged(auto_update) => {
+                // Notifications are only shown when the system is not updating itself.
+                if config.enabled == auto_update {
+                    info!("system auto-update changed to {}", auto_update);
+                    config.enabled = !auto_update;
+
+                    if let Err(why) = config::write_session(&config).await {
+                        error!("failed to write session config: {}", why);
+                    }
+
+                    if let Ok(iface) = connection
+                        .object_server()
+                        .interface::<_, LocalServer>(IFACE_LOCAL)
+                        .await
+                    {
+                        iface.get_mut().await.config = config.clone();
+                    }
+
+                    state.check_for_updates(&config).await;
+                }
+            }
+
             LocalEvent::UpdateConfig(conf) => {
                 config = conf;
                 state.check_for_updates(&config).await;
@@ -81,15 +107,33 @@ pub async fn run() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Forwards changes to the system config from the system service.
+async fn system_config_changes(proxy: ClientProxy<'static>, sender: Sender<LocalEvent>) {
+    let mut changes = proxy.receive_auto_update_changed().await;
+
+    while let Some(change) = changes.next().await {
+        if let Ok(auto_update) = change.get().await {
+            let event = LocalEvent::SystemConfigChanged(auto_update);
+            if sender.send_async(event).await.is_err() {
+                break;
+            }
+        }
+    }
+}
+
 pub struct State {
+    system_proxy: ClientProxy<'static>,
     cache: LocalCache,
-    schedule_handle: JoinHandle<()>,
+    scheduler: Scheduler<chrono::Local>,
+    check_job: Option<JobId>,
     sender: Sender<LocalEvent>,
 }
 
 impl State {
     async fn check_for_updates(&mut self, config: &Local) {
-        self.schedule_handle.abort();
+        if let Some(id) = self.check_job.take() {
+            self.scheduler.remove(id);
+        }
 
         if !config.enabled {
             info!("notifications disabled");
@@ -105,31 +149,61 @@ impl State {
         if next_update > now {
             let next = next_update - now;
             info!("next update in {} seconds", next);
-            let future = update_on(self.sender.clone(), Duration::from_secs(next));
-            self.schedule_handle = tokio::spawn(future);
+            self.check_in(Duration::from_secs(next));
             return;
         }
 
-        self.schedule_handle = tokio::spawn(update_on(
-            self.sender.clone(),
-            Duration::from_secs(SECONDS_IN_DAY),
-        ));
+        self.check_in(Duration::from_secs(SECONDS_IN_DAY));
 
         self.cache.last_update = now;
-        let f1 = config::write_session_cache(&self.cache);
-        let f2 = async {
-            if crate::package_managers::updates_are_available().await {
-                info!("displaying notification of available updates");
+        let f1 = async {
+            if let Err(why) = config::write_session_cache(&self.cache).await {
+                error!("failed to write session cache: {}", why);
+            }
+        };
+        let mut proxy = self.system_proxy.clone();
+        let f2 = async move {
+            let count = match proxy.list_updates().await {
+                Ok(available) => available
+                    .iter()
+                    .filter(|a| a.automatic)
+                    .map(|a| a.updates.len())
+                    .sum(),
+                Err(why) => {
+                    error!("failed to list available updates: {}", why);
+                    0
+                }
+            };
+
+            if count != 0 {
+                info!("displaying notification of {} available updates", count);
                 let handle = tokio::runtime::Handle::current();
                 std::thread::spawn(move || {
                     let _reactor_context = handle.enter();
-                    crate::notify::updates_available();
+                    crate::notify::updates_available(count);
                 });
             }
         };
 
         futures::join!(f1, f2);
     }
+
+    /// Schedules the next check for updates after the given delay.
+    fn check_in(&mut self, delay: Duration) {
+        let at = chrono::Duration::from_std(delay)
+            .ok()
+            .and_then(|delay| chrono::Local::now().checked_add_signed(delay));
+
+        if let Some(at) = at {
+            let sender = self.sender.clone();
+            self.check_job = Some(self.scheduler.insert_async(Job::once(at), move |_| {
+                let sender = sender.clone();
+                async move {
+                    let _ = sender.send_async(LocalEvent::CheckUpdates).await;
+                }
+            }));
+        }
+    }
 }
 
 const SECONDS_IN_DAY: u64 = 60 * 60 * 24;
@@ -141,8 +215,3 @@ fn next_update(config: &Local, cache: &LocalCache) -> u64 {
         Frequency::Monthly => cache.last_update + SECONDS_IN_DAY * 30,
     }
 }
-
-async fn update_on(sender: Sender<LocalEvent>, duration: Duration) {
-    tokio::time::sleep(duration).await;
-    let _ = sender.send_async(LocalEvent::CheckUpdates).await;
-}
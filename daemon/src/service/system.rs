@@ -1,35 +1,88 @@
 // Copyright 2021-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MPL-2.0
 
+use crate::inotify::Watcher;
+use crate::package_managers::Registry;
+use crate::runner::Runner;
+use crate::signal_handler::Signal;
 use anyhow::Context;
-use async_cron_scheduler::{Job, JobId, Scheduler};
-use chrono::Local;
-use config::{Interval, Schedule};
+use async_cron_scheduler::{Job, JobId, Overlap, RunStore, Scheduler};
+use chrono::{DateTime, Local, TimeZone, Utc};
+use config::{Backends, Cache, Interval, Schedule};
 use flume::Sender;
 use futures::StreamExt;
-use pop_system_updater::config::{self, Config};
+use pop_system_updater::config::{self, Config, Key, Layer, Layered};
+use pop_system_updater::dbus::rate_limit::RateLimit;
 use pop_system_updater::dbus::PopService;
 use pop_system_updater::dbus::{
     server::{self, Server},
     Event, IFACE,
 };
+use pop_system_updater::history::{self, Report, Source};
+use pop_system_updater::polkit::Authority;
+use pop_system_updater::prefetch;
+use std::convert::TryFrom;
 use std::path::Path;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::task::JoinHandle;
 use zbus::Connection;
 
 pub struct Service {
+    connection: Connection,
     updating: Arc<AtomicBool>,
+    next_update: Arc<AtomicU64>,
     update_task: Option<JoinHandle<()>>,
+    /// Downloads updates in the background, ahead of the next update.
+    prefetch_task: Option<JoinHandle<()>>,
     update_job: Option<JobId>,
-    when_available_queue: Option<JoinHandle<()>>,
+    /// Minutes that the next scheduled update has been postponed by.
+    deferred: u32,
+    when_available_queue: Option<JobId>,
     scheduler: Scheduler<Local>,
+    last_update: Arc<LastUpdate>,
+}
+
+/// Persists the time of the last system update in the system cache.
+pub struct LastUpdate(Arc<AtomicU64>);
+
+impl RunStore for LastUpdate {
+    fn last_run(&self) -> Option<DateTime<Utc>> {
+        match self.0.load(Ordering::SeqCst) {
+            0 => None,
+            seconds => i64::try_from(seconds)
+                .ok()
+                .and_then(|seconds| Utc.timestamp_opt(seconds, 0).single()),
+        }
+    }
+
+    fn set_last_run(&self, time: DateTime<Utc>) {
+        let last_update = u64::try_from(time.timestamp()).unwrap_or(0);
+        self.0.store(last_update, Ordering::SeqCst);
+
+        tokio::spawn(async move {
+            let cache = Cache {
+                last_update,
+                ..Cache::default()
+            };
+
+            if let Err(why) = config::write_system_cache(&cache).await {
+                error!("failed to write system cache: {}", why);
+            }
+        });
+    }
+}
+
+/// Properties of the server whose values are shared with the service.
+enum Shared {
+    LastUpdate,
+    NextScheduledUpdate,
+    Updating,
 }
 
 impl Service {
-    async fn auto_update(&mut self, connection: &zbus::Connection, sender: Sender<Event>) {
+    async fn auto_update(&mut self, sender: Sender<Event>, backends: Backends) {
         if self.update_task.is_some() {
             info!("already performing an update");
             return;
@@ -37,38 +90,80 @@ impl Service {
 
         info!("system update initiated");
         self.updating.store(true, Ordering::SeqCst);
+        self.shared_changed(Shared::Updating).await;
 
-        let connection = connection.clone();
+        let connection = self.connection.clone();
         let updating = self.updating.clone();
+        let prefetch_task = self.prefetch_task.take();
 
         self.update_task = Some(tokio::task::spawn(async move {
-            let _ = futures::join!(
-                crate::package_managers::apt::update(connection.clone()),
-                crate::package_managers::flatpak::update(connection.clone()),
-                crate::package_managers::fwupd::update(connection.clone()),
-                crate::package_managers::nix::update(&connection),
-                crate::package_managers::snap::update(connection.clone())
-            );
+            // Downloads in progress are finished, instead of started over.
+            if let Some(task) = prefetch_task {
+                info!("waiting for updates to finish downloading");
+                let _res = task.await;
+            }
+
+            let started = u64::try_from(Utc::now().timestamp()).unwrap_or(0);
+            let runner = Runner::for_update(connection.clone(), started).await;
+
+            let sources = Registry::system(&backends).update(&runner).await;
+            prefetch::clear().await;
+
+            let succeeded = sources.iter().all(Source::succeeded);
+
+            let report = Report {
+                started,
+                finished: u64::try_from(Utc::now().timestamp()).unwrap_or(0),
+                sources,
+                ..Report::default()
+            };
+
+            if let Err(why) = history::write(&report).await {
+                error!("failed to write update report: {}", why);
+            }
+
+            server::context(&connection, |ctx| async move {
+                Server::update_finished(&ctx, &report).await
+            })
+            .await;
 
             updating.store(false, Ordering::SeqCst);
-            let _ = sender.send_async(Event::UpdateComplete).await;
+            let _ = sender.send_async(Event::UpdateComplete(succeeded)).await;
             info!("system update complete");
         }));
     }
 
-    async fn check_for_updates(&self) {
+    async fn check_for_updates(&self, backends: &Backends) {
         if self.update_task.is_some() {
             info!("already performing an update");
             return;
         }
 
+        if self.prefetch_task.is_some() {
+            info!("already downloading updates");
+            return;
+        }
+
         info!("checking for system updates");
-        apt_cmd::lock::apt_lock_wait().await;
-        crate::package_managers::apt::update_package_lists().await;
+
+        // Refreshed package lists may need packages that were not downloaded.
+        prefetch::clear().await;
+
+        Registry::system(backends)
+            .refresh(&Runner::new(self.connection.clone()))
+            .await;
         info!("check for system updates complete");
+
+        let now = u64::try_from(Utc::now().timestamp()).unwrap_or(0);
+        if let Some(iface) = server::interface(&self.connection).await {
+            let ctx = iface.signal_context();
+            if let Err(why) = iface.get().await.set_last_check(ctx, now).await {
+                error!("failed to signal last check: {}", why);
+            }
+        }
     }
 
-    async fn repair(&self, connection: &zbus::Connection) {
+    async fn repair(&self, backends: &Backends) {
         if self.update_task.is_some() {
             info!("already performing an update");
             return;
@@ -76,7 +171,9 @@ impl Service {
 
         info!("performing a system repair");
 
-        let result = crate::package_managers::apt::repair().await;
+        let result = Registry::system(backends)
+            .repair(&Runner::new(self.connection.clone()))
+            .await;
 
         let response = |ctx| async move {
             match result {
@@ -85,80 +182,292 @@ impl Service {
             }
         };
 
-        server::context(connection, response).await;
+        server::context(&self.connection, response).await;
 
         info!("system repair attempt complete");
     }
 
+    /// Downloads the updates which were found, so that the next update only installs them.
+    fn prefetch(&mut self, sender: Sender<Event>, backends: Backends) {
+        if self.prefetch_task.is_some() {
+            return;
+        }
+
+        info!("downloading updates ahead of time");
+        let connection = self.connection.clone();
+
+        self.prefetch_task = Some(tokio::task::spawn(async move {
+            // Progress is only signaled for updates, which clients present as such.
+            Registry::system(&backends)
+                .prefetch(&Runner::background(connection))
+                .await;
+
+            let _ = sender.send_async(Event::PrefetchComplete).await;
+            info!("finished downloading updates");
+        }));
+    }
+
     fn schedule_when_available(&mut self, sender: &Sender<Event>) {
         if let Some(id) = self.update_job.take() {
             self.scheduler.remove(id);
         }
 
-        if let Some(task) = self.when_available_queue.take() {
-            task.abort();
+        if let Some(id) = self.when_available_queue.take() {
+            self.scheduler.remove(id);
         }
 
         self.update_job = Some(auto_job(&mut self.scheduler, sender));
     }
 
-    async fn update_notification(&self, connection: &zbus::Connection) {
-        let response = |ctx| async move {
-            Server::updates_available(&ctx, crate::package_managers::updates_are_available().await)
-                .await
+    /// Moves the next scheduled update back by the given number of minutes.
+    async fn postpone(&mut self, minutes: u32) {
+        let id = match self.update_job {
+            Some(id) => id,
+            None => {
+                info!("no update is scheduled to postpone");
+                return;
+            }
+        };
+
+        if let Some(next) = self.scheduler.next_occurrence(id).await {
+            let until = next + chrono::Duration::minutes(i64::from(minutes));
+            info!("postponing the next update to {}", until);
+            self.scheduler.postpone(id, until);
+            self.deferred = self.deferred.saturating_add(minutes);
+        }
+    }
+
+    fn skip_next(&self) {
+        match self.update_job {
+            Some(id) => {
+                info!("skipping the next scheduled update");
+                self.scheduler.skip_next(id);
+            }
+            None => info!("no update is scheduled to skip"),
+        }
+    }
+
+    /// Stores the time of the next scheduled update for the DBus service to report.
+    async fn refresh_next_update(&self) {
+        let next = match self.update_job {
+            Some(id) => self.scheduler.next_occurrence(id).await,
+            None => None,
         };
 
-        server::context(connection, response).await;
+        let next_update = next
+            .and_then(|time| u64::try_from(time.timestamp()).ok())
+            .unwrap_or(0);
+
+        if self.next_update.swap(next_update, Ordering::SeqCst) != next_update {
+            self.shared_changed(Shared::NextScheduledUpdate).await;
+        }
+    }
+
+    /// Changes the properties of the server that reflect the system config.
+    async fn config_changed(&self, config: &Config) {
+        if let Some(iface) = server::interface(&self.connection).await {
+            let ctx = iface.signal_context();
+            if let Err(why) = iface.get().await.set_config(ctx, config).await {
+                error!("failed to signal config change: {}", why);
+            }
+        }
+    }
+
+    /// Signals that a property which the server shares with the service has changed.
+    async fn shared_changed(&self, property: Shared) {
+        if let Some(iface) = server::interface(&self.connection).await {
+            let ctx = iface.signal_context();
+            let server = iface.get().await;
+
+            let result = match property {
+                Shared::LastUpdate => server.last_update_changed(ctx).await,
+                Shared::NextScheduledUpdate => server.next_scheduled_update_changed(ctx).await,
+                Shared::Updating => server.updating_changed(ctx).await,
+            };
+
+            if let Err(why) = result {
+                error!("failed to signal property change: {}", why);
+            }
+        }
+    }
+
+    /// Signals if updates are available which a system update would install, and returns it.
+    async fn update_notification(&self, backends: &Backends) -> bool {
+        let runner = Runner::new(self.connection.clone());
+        let available = Registry::system(backends)
+            .pending(&runner)
+            .await
+            .iter()
+            .any(|available| available.automatic);
+
+        if let Some(iface) = server::interface(&self.connection).await {
+            let ctx = iface.signal_context();
+            let result = iface
+                .get()
+                .await
+                .set_updates_available(ctx, available)
+                .await;
+
+            if let Err(why) = result {
+                error!("failed to signal available updates: {}", why);
+            }
+        }
+
+        server::context(&self.connection, |ctx| async move {
+            Server::updates_available(&ctx, available).await
+        })
+        .await;
+
+        available
     }
 
     fn update_scheduler(&mut self, config: &Config, sender: &Sender<Event>) {
+        self.deferred = 0;
+
         if let Some(id) = self.update_job.take() {
             self.scheduler.remove(id);
         }
 
-        if let Some(task) = self.when_available_queue.take() {
-            task.abort();
+        if let Some(id) = self.when_available_queue.take() {
+            self.scheduler.remove(id);
         }
 
         if config.auto_update {
             if let Some(ref conf) = config.schedule {
-                self.update_job = Some(schedule_job(&mut self.scheduler, conf, sender));
+                self.update_job = Some(schedule_job(
+                    &mut self.scheduler,
+                    conf,
+                    &self.last_update,
+                    sender,
+                ));
             } else {
+                info!("scheduling when available in 60 seconds");
                 let sender = sender.clone();
-                self.when_available_queue = Some(tokio::task::spawn(async move {
-                    info!("scheduling when available in 60 seconds");
-                    tokio::time::sleep(Duration::from_secs(60)).await;
-                    let _ = sender.send_async(Event::ScheduleWhenAvailable).await;
+                let job = Job::once(Local::now() + chrono::Duration::seconds(60));
+                self.when_available_queue = Some(self.scheduler.insert_async(job, move |_| {
+                    let sender = sender.clone();
+                    async move {
+                        let _ = sender.send_async(Event::ScheduleWhenAvailable).await;
+                    }
                 }));
             }
         }
     }
 }
 
-// Watches for an interrupt signal.
-async fn interrupt_handler(sender: Sender<Event>) {
+// Watches for signals. A hangup reloads the config, and any other signal exits.
+async fn signal_handler(sender: Sender<Event>) {
     let sig_duration = std::time::Duration::from_secs(1);
 
     loop {
         tokio::time::sleep(sig_duration).await;
-        if crate::signal_handler::status().is_some() {
-            info!("Found interrupt");
-            let _ = sender.send_async(Event::Exit).await;
-            break;
+        match crate::signal_handler::status() {
+            Some(Signal::Hangup) => {
+                info!("Found hangup");
+                let _ = sender.send_async(Event::Reload).await;
+            }
+
+            Some(signal) => {
+                info!("Found {}", signal);
+                let _ = sender.send_async(Event::Exit).await;
+                break;
+            }
+
+            None => (),
         }
     }
 }
 
-// Check for updates every 12 hours.
-async fn scheduled_check(sender: Sender<Event>) {
-    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60 * 60 * 12));
+// Reloads the config when any of its layers are changed on disk.
+async fn config_watcher(sender: Sender<Event>) {
+    let watcher = match Watcher::new() {
+        Ok(watcher) => watcher,
+        Err(why) => {
+            error!("could not watch for config changes: {}", why);
+            return;
+        }
+    };
+
+    if let Err(why) = tokio::fs::create_dir_all(config::DROP_IN_DIR).await {
+        error!("could not create {}: {}", config::DROP_IN_DIR, why);
+    }
+
+    let system_dir = Path::new(config::SYSTEM_PATH).parent();
+    let vendor_dir = Path::new(config::VENDOR_PATH).parent();
+
+    let directories = [system_dir, vendor_dir, Some(Path::new(config::DROP_IN_DIR))];
+
+    for directory in directories.iter().flatten() {
+        if let Err(why) = watcher.watch(directory) {
+            info!("not watching {:?} for config changes: {}", directory, why);
+        }
+    }
 
     loop {
-        interval.tick().await;
-        let _ = sender.send_async(Event::CheckForUpdates).await;
+        match watcher.changed().await {
+            Ok(names) => {
+                if !names
+                    .iter()
+                    .any(|name| Path::new(name).extension() == Some("ron".as_ref()))
+                {
+                    continue;
+                }
+
+                // Editors and package managers change files in several steps.
+                tokio::time::sleep(Duration::from_millis(500)).await;
+                let _ = sender.send_async(Event::Reload).await;
+            }
+
+            Err(why) => {
+                error!("stopped watching for config changes: {}", why);
+                return;
+            }
+        }
+    }
+}
+
+// Re-arms the scheduler when the system resumes from suspend.
+async fn resume_handler(connection: Connection, sender: Sender<Event>) {
+    let proxy = match crate::login1::ManagerProxy::new(&connection).await {
+        Ok(proxy) => proxy,
+        Err(why) => {
+            error!("could not connect to logind: {}", why);
+            return;
+        }
+    };
+
+    let mut signals = match proxy.receive_prepare_for_sleep().await {
+        Ok(signals) => signals,
+        Err(why) => {
+            error!("could not listen for suspend signals from logind: {}", why);
+            return;
+        }
+    };
+
+    while let Some(signal) = signals.next().await {
+        if let Ok(args) = signal.args() {
+            if !args.start {
+                let _ = sender.send_async(Event::Resume).await;
+            }
+        }
     }
 }
 
+// Check for updates now, and every 12 hours after.
+fn scheduled_check(scheduler: &mut Scheduler<Local>, sender: &Sender<Event>) -> JobId {
+    let sender = sender.clone();
+    let job = Job::interval(Duration::from_secs(60 * 60 * 12)).overlap(Overlap::Skip);
+    let id = scheduler.insert_async(job, move |_| {
+        let sender = sender.clone();
+        async move {
+            let _ = sender.send_async(Event::CheckForUpdates).await;
+        }
+    });
+
+    scheduler.trigger(id);
+    id
+}
+
 pub async fn run() -> anyhow::Result<()> {
     info!("initiating system service");
     crate::signal_handler::init();
@@ -166,6 +475,16 @@ pub async fn run() -> anyhow::Result<()> {
     let (sender, receiver) = flume::bounded(1);
 
     let updating = Arc::new(AtomicBool::new(false));
+    let next_update = Arc::new(AtomicU64::new(0));
+
+    let (mut layers, cache) =
+        futures::join!(config::load_system_layers(), config::load_system_cache());
+
+    for key in Key::ALL {
+        info!("{:?} is set by {}", key, layers.origin(key));
+    }
+
+    let last_update = Arc::new(AtomicU64::new(cache.last_update));
 
     let connection = Connection::system()
         .await
@@ -176,10 +495,17 @@ pub async fn run() -> anyhow::Result<()> {
         .at(
             IFACE,
             Server {
+                config: Mutex::new(layers.config.clone()),
+                last_check: AtomicU64::new(0),
+                last_update: last_update.clone(),
+                updates_available: AtomicBool::new(false),
                 updating: updating.clone(),
+                next_update: next_update.clone(),
                 service: PopService {
                     sender: sender.clone(),
                 },
+                authority: Authority::Polkit(connection.clone()),
+                queries: RateLimit::new(),
             },
         )
         .await
@@ -195,70 +521,232 @@ pub async fn run() -> anyhow::Result<()> {
 
     info!("DBus connection established");
 
-    let mut config = config::load_system().await;
-
     let (scheduler, scheduler_service) = Scheduler::<Local>::launch(tokio::time::sleep);
 
     let mut service = Service {
+        connection: connection.clone(),
         updating,
+        next_update,
         update_job: None,
+        deferred: 0,
         update_task: None,
+        prefetch_task: None,
         when_available_queue: None,
         scheduler,
+        last_update: Arc::new(LastUpdate(last_update)),
     };
 
-    service.update_scheduler(&config, &sender);
+    service.update_scheduler(&layers.config, &sender);
+    scheduled_check(&mut service.scheduler, &sender);
 
     futures::join!(
         scheduler_service,
-        interrupt_handler(sender.clone()),
+        signal_handler(sender.clone()),
+        config_watcher(sender.clone()),
+        resume_handler(connection.clone(), sender.clone()),
         restart_session_services(),
-        scheduled_check(sender.clone()),
         // The event handler, which processes all requests from DBus and the scheduler.
         async move {
+            service.refresh_next_update().await;
+
             info!("listening for events");
             while let Ok(event) = receiver.recv_async().await {
                 info!("received event: {:?}", event);
                 match event {
                     Event::CheckForUpdates => {
-                        service.check_for_updates().await;
-                        service.update_notification(&connection).await;
+                        service.check_for_updates(&layers.config.backends).await;
+
+                        let available = service.update_notification(&layers.config.backends).await;
+
+                        if available && service.update_task.is_none() {
+                            service.prefetch(sender.clone(), layers.config.backends.clone());
+                        }
                     }
 
-                    Event::Repair => service.repair(&connection).await,
+                    Event::ConfigLayers(reply) => {
+                        let keys = Key::ALL
+                            .iter()
+                            .map(|&key| {
+                                let origin = layers.origin(key).to_string();
+                                let locked =
+                                    layers.is_locked(key) || layers.overridden_by(key).is_some();
+                                (format!("{:?}", key), origin, locked)
+                            })
+                            .collect();
+
+                        let _res = reply.send_async(Ok(keys)).await;
+                    }
 
-                    Event::ScheduleWhenAvailable => service.schedule_when_available(&sender),
+                    Event::ListUpdates(reply) => {
+                        let runner = Runner::new(service.connection.clone());
+                        let backends = layers.config.backends.clone();
 
-                    Event::Update => service.auto_update(&connection, sender.clone()).await,
+                        // Listing may take a while, and should not hold up other requests.
+                        tokio::spawn(async move {
+                            let available = Registry::system(&backends).pending(&runner).await;
+                            let _res = reply.send_async(Ok(available)).await;
+                        });
+                    }
 
-                    Event::UpdateComplete => service.update_task = None,
+                    Event::Postpone(minutes, reply) => {
+                        let result = async {
+                            let policy = &layers.config.policy;
+                            if policy.deferral_limited()
+                                && service.deferred.saturating_add(minutes) > policy.max_deferral
+                            {
+                                return Err(denied(format!(
+                                    "updates may only be postponed by {} minutes",
+                                    policy.max_deferral
+                                )));
+                            }
+
+                            service.postpone(minutes).await;
+                            service.refresh_next_update().await;
+                            Ok(())
+                        };
+
+                        let _res = reply.send_async(result.await).await;
+                    }
 
-                    Event::SetAutoUpdate(enable) => {
-                        info!("setting auto-update mode to {}", enable);
+                    Event::Reload => {
+                        let reloaded = config::load_system_layers().await;
+                        let changed = reloaded.config != layers.config;
+                        layers = reloaded;
+
+                        if changed {
+                            info!("system configuration reloaded: {:?}", layers.config);
+                            service.update_scheduler(&layers.config, &sender);
+                            service.refresh_next_update().await;
+                            service.config_changed(&layers.config).await;
+                        }
+                    }
 
-                        config.auto_update = enable;
+                    Event::Repair => service.repair(&layers.config.backends).await,
 
-                        service.update_scheduler(&config, &sender);
+                    Event::Resume => {
+                        info!("system resumed from suspend");
+                        service.scheduler.resync();
+                        service.refresh_next_update().await;
+                    }
 
-                        let config = config.clone();
-                        tokio::spawn(async move {
-                            config::write_system(&config).await;
-                            info!("system configuration file updated");
-                        });
+                    Event::ScheduleWhenAvailable => {
+                        service.schedule_when_available(&sender);
+                        service.refresh_next_update().await;
                     }
 
-                    Event::SetSchedule(schedule) => {
-                        info!("Changing scheduling to {:?}", schedule);
+                    Event::Update => {
+                        service.deferred = 0;
+                        service
+                            .auto_update(sender.clone(), layers.config.backends.clone())
+                            .await;
+                        service.refresh_next_update().await;
+                    }
 
-                        config.schedule = schedule;
+                    Event::PrefetchComplete => {
+                        service.prefetch_task = None;
+                    }
 
-                        service.update_scheduler(&config, &sender);
+                    Event::UpdateComplete(succeeded) => {
+                        service.update_task = None;
+                        service.shared_changed(Shared::Updating).await;
 
-                        let config = config.clone();
-                        tokio::spawn(async move {
-                            config::write_system(&config).await;
-                            info!("system configuration file updated");
-                        });
+                        // A failed update is caught up on, as if it were missed.
+                        if succeeded {
+                            service.last_update.set_last_run(Utc::now());
+                            service.shared_changed(Shared::LastUpdate).await;
+                        }
+                    }
+
+                    Event::SetAutoUpdate(enable, reply) => {
+                        let result = async {
+                            unlocked(&layers, Key::AutoUpdate)?;
+
+                            if !enable && layers.config.policy.auto_update_required {
+                                return Err(denied("automatic updates are required by policy"));
+                            }
+
+                            info!("setting auto-update mode to {}", enable);
+
+                            layers.update(|layer| layer.auto_update = Some(enable));
+
+                            service.update_scheduler(&layers.config, &sender);
+                            service.refresh_next_update().await;
+
+                            service.config_changed(&layers.config).await;
+                            write_config(layers.system()).await
+                        };
+
+                        let _res = reply.send_async(result.await).await;
+                    }
+
+                    Event::SetBackends(backends, reply) => {
+                        let result = async {
+                            unlocked(&layers, Key::Backends)?;
+
+                            info!("changing backends to {:?}", backends);
+
+                            layers.update(|layer| layer.backends = Some(backends));
+
+                            service.config_changed(&layers.config).await;
+                            write_config(layers.system()).await
+                        };
+
+                        let _res = reply.send_async(result.await).await;
+                    }
+
+                    Event::SetSchedule(schedule, reply) => {
+                        let result = async {
+                            unlocked(&layers, Key::Schedule)?;
+
+                            let min_days = layers.config.policy.min_scheduled_days;
+                            if let Some(ref schedule) = schedule {
+                                if schedule.is_empty() {
+                                    return Err(invalid(
+                                        "updates must be scheduled on at least one day and time",
+                                    ));
+                                }
+
+                                if let Some(time) = schedule.times.iter().find(|t| !t.is_valid()) {
+                                    return Err(invalid(format!(
+                                        "{:02}:{:02} is not a time of day",
+                                        time.hour, time.minute
+                                    )));
+                                }
+
+                                if !layers.config.policy.permits(schedule) {
+                                    return Err(denied(format!(
+                                        "updates must be scheduled on at least {} days of the week",
+                                        min_days
+                                    )));
+                                }
+                            }
+
+                            info!("Changing scheduling to {:?}", schedule);
+
+                            layers.update(|layer| layer.schedule = Some(schedule));
+
+                            service.update_scheduler(&layers.config, &sender);
+                            service.refresh_next_update().await;
+
+                            service.config_changed(&layers.config).await;
+                            write_config(layers.system()).await
+                        };
+
+                        let _res = reply.send_async(result.await).await;
+                    }
+
+                    Event::SkipNext(reply) => {
+                        let result = async {
+                            if layers.config.policy.deferral_limited() {
+                                return Err(denied("updates may not be skipped by policy"));
+                            }
+
+                            service.skip_next();
+                            service.refresh_next_update().await;
+                            Ok(())
+                        };
+
+                        let _res = reply.send_async(result.await).await;
                     }
 
                     Event::Exit => {
@@ -275,6 +763,53 @@ pub async fn run() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// Refuses to change a key which was locked by a vendor or drop-in layer, or
+/// which a drop-in sets over the admin's layer, where a change would have no effect.
+fn unlocked(layers: &Layered, key: Key) -> zbus::fdo::Result<()> {
+    if layers.is_locked(key) {
+        return Err(denied(format!(
+            "{:?} is locked by {}",
+            key,
+            layers.origin(key)
+        )));
+    }
+
+    if let Some(origin) = layers.overridden_by(key) {
+        return Err(denied(format!("{:?} is overridden by {}", key, origin)));
+    }
+
+    Ok(())
+}
+
+/// Refuses a request which the admin's policy does not permit.
+fn denied(why: impl Into<String>) -> zbus::fdo::Error {
+    let why = why.into();
+    info!("refusing request: {}", why);
+    zbus::fdo::Error::AccessDenied(why)
+}
+
+fn invalid(why: impl Into<String>) -> zbus::fdo::Error {
+    let why = why.into();
+    info!("refusing request: {}", why);
+    zbus::fdo::Error::InvalidArgs(why)
+}
+
+async fn write_config(config: &Layer) -> zbus::fdo::Result<()> {
+    match config::write_system(config).await {
+        Ok(()) => {
+            info!("system configuration file updated");
+            Ok(())
+        }
+        Err(why) => {
+            error!("failed to write system configuration: {}", why);
+            Err(zbus::fdo::Error::IOError(format!(
+                "failed to write system configuration: {}",
+                why
+            )))
+        }
+    }
+}
+
 /// Ensures that session services are always updated and restarted along with this service.
 async fn restart_session_services() {
     info!("restarting any session services");
@@ -304,53 +839,92 @@ async fn restart_session_services() {
 
 fn auto_job(scheduler: &mut Scheduler<Local>, sender: &Sender<Event>) -> JobId {
     info!("scheduling every 12 hours");
-    tokio::spawn({
+
+    let sender = sender.clone();
+    let job = Job::cron("0 0 */12 * * *").unwrap().overlap(Overlap::Skip);
+    let id = scheduler.insert_async(job, move |_| {
         let sender = sender.clone();
         async move {
             let _ = sender.send_async(Event::Update).await;
         }
     });
 
-    let sender = sender.clone();
-    scheduler.insert(Job::cron("0 0 */12 * * *").unwrap(), move |_| {
-        let sender = sender.clone();
-        tokio::spawn(async move {
-            let _ = sender.send_async(Event::Update).await;
-        });
-    })
+    scheduler.trigger(id);
+    id
 }
 
 fn schedule_job(
     scheduler: &mut Scheduler<Local>,
     schedule: &Schedule,
+    last_update: &Arc<LastUpdate>,
     sender: &Sender<Event>,
 ) -> JobId {
     info!("scheduling for {:?}", schedule);
+    let mut job = Job::crons(&cron_expressions(schedule))
+        .unwrap()
+        .catch_up(last_update.clone());
+
+    if let Some(window) = update_window(schedule) {
+        job = job.window(window);
+    }
+
     let sender = sender.clone();
-    scheduler.insert(Job::cron(&*cron_expression(schedule)).unwrap(), move |_| {
+    scheduler.insert_async(job.overlap(Overlap::Skip), move |_| {
         let sender = sender.clone();
-        tokio::spawn(async move {
+        async move {
             let _ = sender.send_async(Event::Update).await;
-        });
+        }
     })
 }
 
-fn cron_expression(schedule: &Schedule) -> String {
-    let days: &str = match schedule.interval {
-        Interval::Sunday => "SUN",
-        Interval::Monday => "MON",
-        Interval::Tuesday => "TUE",
-        Interval::Wednesday => "WED",
-        Interval::Thursday => "THU",
-        Interval::Friday => "FRI",
-        Interval::Saturday => "SAT",
-        Interval::Weekdays => "1-5",
-    };
+fn cron_expressions(schedule: &Schedule) -> Vec<String> {
+    if schedule.days.is_empty() {
+        info!("no days are selected for the schedule");
+        return Vec::new();
+    }
+
+    let days = schedule
+        .days
+        .iter()
+        .map(|day| match day {
+            Interval::Sunday => "SUN",
+            Interval::Monday => "MON",
+            Interval::Tuesday => "TUE",
+            Interval::Wednesday => "WED",
+            Interval::Thursday => "THU",
+            Interval::Friday => "FRI",
+            Interval::Saturday => "SAT",
+        })
+        .collect::<Vec<_>>()
+        .join(",");
+
+    schedule
+        .times
+        .iter()
+        .filter(|time| {
+            let valid = time.is_valid();
+            if !valid {
+                info!(
+                    "ignoring {:02}:{:02}, which is not a time of day",
+                    time.hour, time.minute
+                );
+            }
+
+            valid
+        })
+        .map(|time| {
+            let expression = format!("0 {} {} * * {}", time.minute, time.hour, days);
+            info!("setting cron expression {}", expression);
+            expression
+        })
+        .collect()
+}
 
-    let minute = schedule.minute.min(59);
-    let hour = schedule.hour.min(23);
+fn update_window(schedule: &Schedule) -> Option<Duration> {
+    if schedule.window == 0 {
+        return None;
+    }
 
-    let expression = format!("0 {minute} {hour} * * {days}");
-    info!("setting cron expression {}", expression);
-    expression
+    info!("updates may start up to {} minutes late", schedule.window);
+    Some(Duration::from_secs(u64::from(schedule.window) * 60))
 }
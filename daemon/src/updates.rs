@@ -0,0 +1,86 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! Updates which are available to install from each package manager.
+
+use crate::history::Package;
+use serde::{Deserialize, Serialize};
+use zvariant::Type;
+
+/// The updates that a package manager has available to install.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Type)]
+pub struct Available {
+    /// The name of the source, as it is named in progress, errors, and reports.
+    pub source: String,
+    pub updates: Vec<Update>,
+    /// If a system update installs them. Otherwise they are only listed, such as
+    /// firmware, which is installed when the user asks for it.
+    pub automatic: bool,
+}
+
+/// A package, app, or device which has an update available.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Serialize, Type)]
+pub struct Update {
+    pub name: String,
+    /// Empty if it is not installed, or if the package manager does not report it.
+    pub current_version: String,
+    /// Empty if the package manager does not report it.
+    pub candidate_version: String,
+    /// Bytes which will be downloaded, or 0 if unknown.
+    pub download_size: u64,
+    /// If the update is known to fix security issues.
+    pub security: bool,
+}
+
+impl From<Update> for Package {
+    fn from(update: Update) -> Self {
+        Self {
+            name: update.name,
+            old_version: update.current_version,
+            new_version: update.candidate_version,
+        }
+    }
+}
+
+/// Parses a size which was formatted for people, such as `1.2 MB`, `55MB`, or `800 kB`.
+///
+/// Units are decimal unless they are written as binary, such as `MiB`.
+#[must_use]
+pub fn parse_size(size: &str) -> Option<u64> {
+    let size = size.trim();
+    let split = size
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(size.len());
+
+    let (number, unit) = size.split_at(split);
+    let number = number.parse::<f64>().ok()?;
+
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" | "bytes" => 1,
+        "k" | "kb" => 1_000,
+        "m" | "mb" => 1_000_000,
+        "g" | "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return None,
+    };
+
+    Some((number * multiplier as f64) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("1.2 MB"), Some(1_200_000));
+        assert_eq!(parse_size("55MB"), Some(55_000_000));
+        assert_eq!(parse_size("800 kB"), Some(800_000));
+        assert_eq!(parse_size("2 MiB"), Some(2 * 1024 * 1024));
+        assert_eq!(parse_size("512"), Some(512));
+        assert_eq!(parse_size("unknown"), None);
+        assert_eq!(parse_size("3 parsecs"), None);
+    }
+}
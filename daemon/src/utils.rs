@@ -2,8 +2,9 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use anyhow::Context;
-use as_result::IntoResult;
 use pop_system_updater::dbus::server::{context, Server};
+use pop_system_updater::error::{CommandFailed, UpdateError};
+use std::process::Stdio;
 use tokio::process::Command;
 
 pub async fn async_commands(cmds: &[&[&str]]) -> anyhow::Result<()> {
@@ -27,14 +28,31 @@ pub async fn async_command(args: &[&str]) -> anyhow::Result<()> {
         cmd.args(&args[1..]);
     }
 
-    cmd.status()
+    let output = cmd
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::piped())
+        .output()
         .await
-        .and_then(IntoResult::into_result)
         .with_context(|| format!("command execution failed for {:?}", args))?;
 
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    log_stderr(args[0], &stderr);
+
+    if !output.status.success() {
+        return Err(CommandFailed::new(args.join(" "), output.status, &stderr))
+            .with_context(|| format!("command execution failed for {:?}", args));
+    }
+
     Ok(())
 }
 
+/// Logs the errors written by a command, which are captured to classify its failures.
+pub fn log_stderr(program: &str, stderr: &str) {
+    for line in stderr.lines() {
+        info!("{}: {}", program, line);
+    }
+}
+
 pub fn command_exists(cmd: &str) -> bool {
     if let Ok(path) = std::env::var("PATH") {
         for location in path.split(':') {
@@ -47,25 +65,28 @@ pub fn command_exists(cmd: &str) -> bool {
     false
 }
 
-pub async fn error_handler(conn: &zbus::Connection, source: &str, error: anyhow::Error) {
-    use std::fmt::Write;
-
-    let mut output = String::new();
+/// Classifies, logs and signals an error, and returns it.
+pub async fn error_handler(
+    conn: &zbus::Connection,
+    source: &str,
+    error: anyhow::Error,
+) -> UpdateError {
+    let error = UpdateError::from(&error);
 
-    {
-        let mut chain = anyhow::Chain::new(error.as_ref());
-        if let Some(why) = chain.next() {
-            let _ = write!(&mut output, "{}", why);
-            output.push_str(&why.to_string());
-            for why in chain {
-                let _ = write!(&mut output, ": {}", why);
-            }
-        }
-    }
-
-    error!("{}: {}", source, output);
+    error!("{}: {}", source, error);
+    let signaled = &error;
     context(conn, |ctx| async move {
-        Server::error(&ctx, source, &output).await
+        Server::error(
+            &ctx,
+            source,
+            signaled.kind as u32,
+            &signaled.command,
+            &signaled.why,
+            &signaled.output,
+        )
+        .await
     })
     .await;
+
+    error
 }
@@ -1,6 +1,8 @@
 automatic-updates-label = Automatic Updates
 automatically-install-label = Automatically Install
 off = Off
+policy-auto-update-required = Automatic updates are required by your administrator.
+policy-min-scheduled-days = Your administrator requires updates to be scheduled on at least { $days } days of the week.
 schedule-daily = Daily
 schedule-dialog-title = Automatically Install
 schedule-label = Schedule Automatic Updates
@@ -8,14 +10,21 @@ schedule-monthly = Monthly
 schedule-weekly = Weekly
 time-am = AM
 time-friday = Friday
+time-friday-short = Fri
 time-monday = Monday
+time-monday-short = Mon
 time-pm = PM
 time-saturday = Saturday
+time-saturday-short = Sat
 time-sunday = Sunday
+time-sunday-short = Sun
 time-thursday = Thursday
+time-thursday-short = Thu
 time-tuesday = Tuesday
+time-tuesday-short = Tue
 time-wednesday = Wednesday
+time-wednesday-short = Wed
 time-weekdays = Weekdays
 update-notifications-label = Show Update Notifications
 update-when-available = Update when Available
-update-when-available-label = Update when Available
\ No newline at end of file
+update-when-available-label = Update when Available
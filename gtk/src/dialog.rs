@@ -5,7 +5,7 @@ use crate::fl;
 #[allow(clippy::wildcard_imports)]
 use crate::utils::*;
 use gtk::prelude::*;
-use pop_system_updater::config::{Config, Interval, Schedule};
+use pop_system_updater::config::{BitFlags, Config, Interval, Schedule, Time, Versioned};
 use postage::prelude::*;
 use std::convert::TryFrom;
 use std::rc::Rc;
@@ -14,7 +14,7 @@ pub struct Dialog(pub gtk::Dialog);
 
 impl Dialog {
     #[allow(clippy::too_many_lines)]
-    pub fn new(widget: &gtk::Widget, func: impl Fn(Config) + 'static) -> Self {
+    pub fn new(widget: &gtk::Widget, config: Config, func: impl Fn(Config) + 'static) -> Self {
         enum Event {
             AutoUpdateChanged,
             Exit,
@@ -22,7 +22,8 @@ impl Dialog {
         }
 
         let when_available;
-        let interval;
+        let days;
+        let day_picker;
         let hour;
         let minute;
         let time_of_day;
@@ -55,25 +56,36 @@ impl Dialog {
                 }
             });
             ..add(&{
-                interval = cascade! {
-                    gtk::ComboBoxText::new();
-                    ..set_valign(gtk::Align::Center);
-                    ..append_text(&fl!("time-monday"));
-                    ..append_text(&fl!("time-tuesday"));
-                    ..append_text(&fl!("time-wednesday"));
-                    ..append_text(&fl!("time-thursday"));
-                    ..append_text(&fl!("time-friday"));
-                    ..append_text(&fl!("time-saturday"));
-                    ..append_text(&fl!("time-sunday"));
-                    ..append_text(&fl!("time-weekdays"));
+                days = vec![
+                    (Interval::Monday, fl!("time-monday-short")),
+                    (Interval::Tuesday, fl!("time-tuesday-short")),
+                    (Interval::Wednesday, fl!("time-wednesday-short")),
+                    (Interval::Thursday, fl!("time-thursday-short")),
+                    (Interval::Friday, fl!("time-friday-short")),
+                    (Interval::Saturday, fl!("time-saturday-short")),
+                    (Interval::Sunday, fl!("time-sunday-short")),
+                ]
+                .into_iter()
+                .map(|(day, label)| (day, gtk::ToggleButton::with_label(&label)))
+                .collect::<Vec<_>>();
+
+                day_picker = cascade! {
+                    gtk::Box::new(gtk::Orientation::Horizontal, 0);
+                    ..set_halign(gtk::Align::Center);
                 };
 
+                day_picker.style_context().add_class("linked");
+
+                for (_, button) in &days {
+                    day_picker.add(button);
+                }
+
                 schedule_label = gtk::Label::builder()
                     .label(&fl!("schedule-label"))
                     .xalign(0.0)
                     .hexpand(true)
                     .vexpand(true)
-                    .mnemonic_widget(&interval)
+                    .mnemonic_widget(&days[0].1)
                     .build();
 
                 hour = cascade! {
@@ -95,7 +107,6 @@ impl Dialog {
 
                 let times = cascade! {
                     gtk::Box::new(gtk::Orientation::Horizontal, 4);
-                    ..add(&interval);
                     ..add(&*hour);
                     ..add(&*minute);
                     ..add(&time_of_day);
@@ -105,6 +116,7 @@ impl Dialog {
                     option_container();
                     ..attach(&schedule_label, 0, 0, 1, 1);
                     ..attach(&times, 1, 0, 1, 1);
+                    ..attach(&day_picker, 1, 1, 1, 1);
                     ..show_all();
                 }
             });
@@ -135,7 +147,7 @@ impl Dialog {
                 hour.set_sensitive(!insensitive);
                 minute.set_sensitive(!insensitive);
                 time_of_day.set_sensitive(!insensitive);
-                interval.set_sensitive(!insensitive);
+                day_picker.set_sensitive(!insensitive);
 
                 let label_ctx = schedule_label.style_context();
 
@@ -146,32 +158,29 @@ impl Dialog {
                 }
             };
 
-            let config = pop_system_updater::config::load_system().await;
-
             let schedule = match config.schedule.as_ref() {
                 Some(sched) => sched.clone(),
                 None => Config::default_schedule(),
             };
 
-            interval.set_active(Some(match schedule.interval {
-                Interval::Monday => 0,
-                Interval::Tuesday => 1,
-                Interval::Wednesday => 2,
-                Interval::Thursday => 3,
-                Interval::Friday => 4,
-                Interval::Saturday => 5,
-                Interval::Sunday => 6,
-                Interval::Weekdays => 7,
-            }));
+            for (day, button) in &days {
+                button.set_active(schedule.days.contains(*day));
+            }
 
-            let (hour_value, am) = match crate::utils::as_12(schedule.hour) {
+            // Only the first time of the schedule is editable here.
+            let time = schedule.times.first().copied().unwrap_or(Time {
+                hour: 22,
+                minute: 0,
+            });
+
+            let (hour_value, am) = match crate::utils::as_12(time.hour) {
                 (hour, false) => (hour, 0),
                 (hour, true) => (hour, 1),
             };
 
             time_of_day.set_active(Some(am));
             hour.set_value(u32::from(hour_value));
-            minute.set_value(u32::from(schedule.minute));
+            minute.set_value(u32::from(time.minute));
 
             if config.schedule.is_none() {
                 disable_scheduling(true);
@@ -194,10 +203,24 @@ impl Dialog {
                 }
             });
 
-            interval.connect_changed({
-                let update_config = update_config.clone();
-                move |_| update_config()
-            });
+            let day_buttons: Rc<Vec<gtk::ToggleButton>> =
+                Rc::new(days.iter().map(|(_, button)| button.clone()).collect());
+
+            for (_, button) in &days {
+                button.connect_toggled({
+                    let update_config = update_config.clone();
+                    let day_buttons = day_buttons.clone();
+                    move |button| {
+                        // Updates are scheduled on at least one day, so the last may not be deselected.
+                        if !day_buttons.iter().any(ToggleButtonExt::is_active) {
+                            button.set_active(true);
+                            return;
+                        }
+
+                        update_config();
+                    }
+                });
+            }
 
             time_of_day.connect_changed({
                 let update_config = update_config.clone();
@@ -236,27 +259,31 @@ impl Dialog {
                         }
 
                         func(Config {
+                            version: Config::VERSION,
+                            policy: config.policy.clone(),
+                            backends: config.backends.clone(),
                             auto_update: true,
                             schedule: if when_available.is_active() {
                                 None
                             } else {
-                                Some(Schedule {
-                                    interval: match interval.active() {
-                                        Some(0) => Interval::Monday,
-                                        Some(1) => Interval::Tuesday,
-                                        Some(2) => Interval::Wednesday,
-                                        Some(3) => Interval::Thursday,
-                                        Some(4) => Interval::Friday,
-                                        Some(5) => Interval::Saturday,
-                                        Some(6) => Interval::Sunday,
-                                        Some(7) => Interval::Weekdays,
-                                        _ => {
-                                            eprintln!("Unknown interval option selected");
-                                            continue;
-                                        }
-                                    },
+                                let time = Time {
                                     hour,
                                     minute: u8::try_from(minute.value()).unwrap_or(0),
+                                };
+
+                                let mut times = schedule.times.clone();
+                                match times.first_mut() {
+                                    Some(first) => *first = time,
+                                    None => times.push(time),
+                                }
+
+                                Some(Schedule {
+                                    days: days
+                                        .iter()
+                                        .filter(|(_, button)| button.is_active())
+                                        .fold(BitFlags::empty(), |days, (day, _)| days | *day),
+                                    times,
+                                    window: schedule.window,
                                 })
                             },
                         });
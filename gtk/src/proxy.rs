@@ -1,9 +1,10 @@
 // Copyright 2021-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MPL-2.0
 
-use pop_system_updater::config::{Config, Frequency};
+use futures::StreamExt;
+use pop_system_updater::config::{Config, Frequency, Versioned};
 use pop_system_updater::dbus::{client::ClientProxy, local_client::LocalClientProxy};
-use postage::mpsc::{channel, Sender};
+use postage::mpsc::{channel, Receiver, Sender};
 use postage::prelude::*;
 use zbus::Connection;
 
@@ -14,8 +15,13 @@ pub enum Event {
     UpdateConfig(Config),
 }
 
-pub fn initialize_service() -> Sender<Event> {
+/// Spawns the thread which talks to the services.
+///
+/// Returns a sender for requests to the services, and a receiver of the system
+/// config, which is sent once connected, and again whenever it changes.
+pub fn initialize_service() -> (Sender<Event>, Receiver<Config>) {
     let (tx, mut rx) = channel(1);
+    let (config_tx, config_rx) = channel(1);
 
     let background_process = async move {
         let connection = match Connection::system().await {
@@ -50,42 +56,56 @@ pub fn initialize_service() -> Sender<Event> {
             }
         };
 
-        while let Some(event) = rx.recv().await {
-            match event {
-                Event::Exit => break,
-
-                Event::UpdateConfig(config) => {
-                    if let Err(why) = proxy.auto_update_set(config.auto_update).await {
-                        eprintln!("failed to change auto-update setting: {}", why);
-                    }
-
-                    let result = match config.schedule {
-                        Some(schedule) => proxy.update_scheduling_set(schedule).await,
-                        None => proxy.update_scheduling_disable().await,
-                    };
-
-                    if let Err(why) = result {
-                        eprintln!("failed to change scheduling: {}", why);
-                    }
-
-                    if let Err(why) = session_proxy
-                        .notifications_enabled(!config.auto_update)
-                        .await
-                    {
-                        eprintln!(
-                            "failed to set auto-update config with user session service: {:?}",
-                            why
-                        );
+        let watcher = watch_system_config(proxy.clone(), config_tx);
+
+        let requests = async move {
+            while let Some(event) = rx.recv().await {
+                match event {
+                    Event::Exit => break,
+
+                    Event::UpdateConfig(config) => {
+                        // Only changed values are set, since each may prompt for authorization.
+                        let current = system_config(&proxy).await.ok();
+
+                        if current.as_ref().map(|c| c.auto_update) != Some(config.auto_update) {
+                            if let Err(why) = proxy.auto_update_set(config.auto_update).await {
+                                eprintln!("failed to change auto-update setting: {}", why);
+                            }
+                        }
+
+                        if current.map(|c| c.schedule) != Some(config.schedule.clone()) {
+                            let result = match config.schedule {
+                                Some(schedule) => proxy.update_scheduling_set(schedule).await,
+                                None => proxy.update_scheduling_disable().await,
+                            };
+
+                            if let Err(why) = result {
+                                eprintln!("failed to change scheduling: {}", why);
+                            }
+                        }
+
+                        if let Err(why) = session_proxy
+                            .notifications_enabled(!config.auto_update)
+                            .await
+                        {
+                            eprintln!(
+                                "failed to set auto-update config with user session service: {:?}",
+                                why
+                            );
+                        }
                     }
-                }
 
-                Event::SetNotificationFrequency(frequency) => {
-                    if let Err(why) = session_proxy.set_notification_frequency(frequency).await {
-                        eprintln!("failed to update notification frequency: {:?}", why);
+                    Event::SetNotificationFrequency(frequency) => {
+                        if let Err(why) = session_proxy.set_notification_frequency(frequency).await
+                        {
+                            eprintln!("failed to update notification frequency: {:?}", why);
+                        }
                     }
                 }
             }
-        }
+        };
+
+        futures::future::select(Box::pin(watcher), Box::pin(requests)).await;
     };
 
     std::thread::spawn(move || {
@@ -97,5 +117,51 @@ pub fn initialize_service() -> Sender<Event> {
         runtime.block_on(background_process);
     });
 
-    tx
+    (tx, config_rx)
+}
+
+/// Sends the system config whenever any of its properties change.
+async fn watch_system_config(proxy: ClientProxy<'static>, mut config_tx: Sender<Config>) {
+    let changes = futures::stream::select(
+        proxy.receive_auto_update_changed().await.map(|_| ()),
+        futures::stream::select(
+            proxy.receive_schedule_changed().await.map(|_| ()),
+            proxy.receive_policy_changed().await.map(|_| ()),
+        ),
+    );
+
+    futures::pin_mut!(changes);
+
+    loop {
+        match system_config(&proxy).await {
+            Ok(config) => {
+                if config_tx.send(config).await.is_err() {
+                    break;
+                }
+            }
+            Err(why) => eprintln!("failed to get the system config: {}", why),
+        }
+
+        if changes.next().await.is_none() {
+            break;
+        }
+    }
+}
+
+async fn system_config(proxy: &ClientProxy<'_>) -> zbus::Result<Config> {
+    let (auto_update, schedule, policy, backends) = futures::try_join!(
+        proxy.auto_update(),
+        proxy.schedule(),
+        proxy.policy(),
+        proxy.backends()
+    )?;
+
+    Ok(Config {
+        version: Config::VERSION,
+        auto_update,
+        // The service sends an empty schedule when scheduling is disabled.
+        schedule: Some(schedule).filter(|schedule| !schedule.days.is_empty()),
+        policy,
+        backends,
+    })
 }
@@ -8,9 +8,9 @@ use crate::proxy;
 use crate::utils::{glib_send, glib_spawn, option_container, option_frame, separator_header};
 use gtk::prelude::*;
 use i18n_embed::DesktopLanguageRequester;
-use pop_system_updater::config::{Config, Frequency, Interval};
+use pop_system_updater::config::{BitFlags, Config, Frequency, Interval, Time, WEEKDAYS};
 use postage::prelude::*;
-use std::cell::Cell;
+use std::cell::{Cell, RefCell};
 use std::rc::Rc;
 
 #[derive(Debug)]
@@ -19,6 +19,7 @@ enum Event {
     ChangeNotificationSchedule,
     DialogComplete(Config),
     Exit,
+    SystemConfig(Config),
 }
 
 #[allow(clippy::module_name_repetitions)]
@@ -44,10 +45,21 @@ impl SettingsWidget {
             );
         }
 
-        let mut ptx = crate::proxy::initialize_service();
+        let (mut ptx, mut config_rx) = crate::proxy::initialize_service();
 
         let (tx, mut rx) = postage::mpsc::channel(1);
 
+        glib_spawn({
+            let mut tx = tx.clone();
+            async move {
+                while let Some(config) = config_rx.recv().await {
+                    if tx.send(Event::SystemConfig(config)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+
         let automatic_updates;
         let schedule_label;
         let schedule_description;
@@ -149,12 +161,14 @@ impl SettingsWidget {
         };
 
         let dialog_active = Rc::new(Cell::new(true));
+        let system_config = Rc::new(RefCell::new(Config::default()));
 
         content.connect_row_activated(
-            glib::clone!(@weak dialog_active, @strong tx, @strong ptx => move |_, row| {
+            glib::clone!(@weak dialog_active, @strong system_config, @strong tx, @strong ptx => move |_, row| {
                 if dialog_active.get() && row.index() == 1 {
                     let tx = tx.clone();
-                    let dialog = Dialog::new(row.upcast_ref::<gtk::Widget>(), move |conf| {
+                    let config = system_config.borrow().clone();
+                    let dialog = Dialog::new(row.upcast_ref::<gtk::Widget>(), config, move |conf| {
                         glib_send(tx.clone(), Event::DialogComplete(conf));
                     });
 
@@ -181,26 +195,10 @@ impl SettingsWidget {
             let update_schedule_description = |config: &Config| {
                 let text: String = if config.auto_update {
                     if let Some(schedule) = config.schedule.as_ref() {
-                        let (hour, am_pm) = match crate::utils::as_12(schedule.hour) {
-                            (hour, false) => (hour, fl!("time-am")),
-                            (hour, true) => (hour, fl!("time-pm")),
-                        };
-
                         format!(
-                            "Update on {} at {:02}:{:02} {}",
-                            match schedule.interval {
-                                Interval::Monday => fl!("time-monday"),
-                                Interval::Tuesday => fl!("time-tuesday"),
-                                Interval::Wednesday => fl!("time-wednesday"),
-                                Interval::Thursday => fl!("time-thursday"),
-                                Interval::Friday => fl!("time-friday"),
-                                Interval::Saturday => fl!("time-saturday"),
-                                Interval::Sunday => fl!("time-sunday"),
-                                Interval::Weekdays => fl!("time-weekdays"),
-                            },
-                            hour,
-                            schedule.minute,
-                            am_pm
+                            "Update on {} at {}",
+                            describe_days(schedule.days),
+                            describe_times(&schedule.times)
                         )
                     } else {
                         fl!("update-when-available")
@@ -233,13 +231,9 @@ impl SettingsWidget {
                 update_schedule_description(config);
             };
 
-            let (mut system_config, session_config) = futures::join!(
-                pop_system_updater::config::load_system(),
-                pop_system_updater::config::load_session()
-            );
+            let session_config = pop_system_updater::config::load_session().await;
 
-            automatic_updates.set_active(system_config.auto_update);
-            change_scheduling_sensitivity(&system_config);
+            change_scheduling_sensitivity(&system_config.borrow());
 
             notification_schedule.set_active(Some(session_config.notification_frequency as u32));
 
@@ -266,7 +260,7 @@ impl SettingsWidget {
                     }
 
                     Event::DialogComplete(conf) => {
-                        system_config = conf.clone();
+                        *system_config.borrow_mut() = conf.clone();
 
                         change_scheduling_sensitivity(&conf);
 
@@ -274,13 +268,45 @@ impl SettingsWidget {
                     }
 
                     Event::AutomaticUpdatesToggled => {
-                        system_config.auto_update = automatic_updates.is_active();
+                        let config = {
+                            let mut config = system_config.borrow_mut();
 
-                        change_scheduling_sensitivity(&system_config);
+                            // The switch was set to the value reported by the service.
+                            if config.auto_update == automatic_updates.is_active() {
+                                continue;
+                            }
 
-                        let _ = ptx
-                            .send(proxy::Event::UpdateConfig(system_config.clone()))
-                            .await;
+                            config.auto_update = automatic_updates.is_active();
+                            config.clone()
+                        };
+
+                        change_scheduling_sensitivity(&config);
+
+                        let _ = ptx.send(proxy::Event::UpdateConfig(config)).await;
+                    }
+
+                    Event::SystemConfig(config) => {
+                        let policy = &config.policy;
+
+                        automatic_updates.set_sensitive(!policy.auto_update_required);
+                        automatic_updates.set_tooltip_text(
+                            Some(fl!("policy-auto-update-required"))
+                                .filter(|_| policy.auto_update_required)
+                                .as_deref(),
+                        );
+
+                        schedule_label.set_tooltip_text(
+                            Some(fl!(
+                                "policy-min-scheduled-days",
+                                days = policy.min_scheduled_days
+                            ))
+                            .filter(|_| policy.min_scheduled_days > 0)
+                            .as_deref(),
+                        );
+
+                        change_scheduling_sensitivity(&config);
+                        *system_config.borrow_mut() = config.clone();
+                        automatic_updates.set_active(config.auto_update);
                     }
 
                     Event::Exit => break,
@@ -291,3 +317,37 @@ impl SettingsWidget {
         widget
     }
 }
+
+fn describe_days(days: BitFlags<Interval>) -> String {
+    if days == WEEKDAYS {
+        return fl!("time-weekdays");
+    }
+
+    days.iter()
+        .map(|day| match day {
+            Interval::Monday => fl!("time-monday"),
+            Interval::Tuesday => fl!("time-tuesday"),
+            Interval::Wednesday => fl!("time-wednesday"),
+            Interval::Thursday => fl!("time-thursday"),
+            Interval::Friday => fl!("time-friday"),
+            Interval::Saturday => fl!("time-saturday"),
+            Interval::Sunday => fl!("time-sunday"),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn describe_times(times: &[Time]) -> String {
+    times
+        .iter()
+        .map(|time| {
+            let (hour, am_pm) = match crate::utils::as_12(time.hour) {
+                (hour, false) => (hour, fl!("time-am")),
+                (hour, true) => (hour, fl!("time-pm")),
+            };
+
+            format!("{:02}:{:02} {}", hour, time.minute, am_pm)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
@@ -0,0 +1,247 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+use cron::error::{Error, ErrorKind};
+use std::str::FromStr;
+
+/// Parses a systemd calendar event expression, as used by `OnCalendar=` in timer units.
+///
+/// The normalized form is `DayOfWeek Year-Month-Day Hour:Minute:Second`, where
+/// any part may be omitted. Each component may be a value, a `*`, a list of
+/// values separated by `,`, a range given as `start..end`, or a repetition given
+/// as `start/step`. The shorthands `minutely`, `hourly`, `daily`, `weekly`,
+/// `monthly`, `quarterly`, `semiannually`, `yearly` and `annually` are also
+/// understood. Time zones and the last day of the month (`~`) are not supported.
+///
+/// See `systemd.time(7)` for the details of the format.
+///
+/// # Errors
+///
+/// Errors if the expression is invalid, or uses a feature that is not supported.
+pub(crate) fn parse(expression: &str) -> Result<cron::Schedule, Error> {
+    let expression = expression.trim();
+
+    if expression.is_empty() {
+        return Err(ErrorKind::Expression("the calendar expression is empty".to_owned()).into());
+    }
+
+    let normalized = match expression.to_ascii_lowercase().as_str() {
+        "minutely" => "*-*-* *:*:00",
+        "hourly" => "*-*-* *:00:00",
+        "daily" => "*-*-* 00:00:00",
+        "weekly" => "Mon *-*-* 00:00:00",
+        "monthly" => "*-*-01 00:00:00",
+        "quarterly" => "*-01,04,07,10-01 00:00:00",
+        "semiannually" => "*-01,07-01 00:00:00",
+        "yearly" | "annually" => "*-01-01 00:00:00",
+        _ => expression,
+    };
+
+    let mut weekdays = "*".to_owned();
+    let mut date = None;
+    let mut time = None;
+
+    for (position, token) in normalized.split_whitespace().enumerate() {
+        if position == 0 && token.starts_with(|c: char| c.is_ascii_alphabetic()) {
+            weekdays = component(token);
+        } else if token.contains(':') && time.is_none() {
+            time = Some(token);
+        } else if token.contains('-') && date.is_none() && time.is_none() {
+            date = Some(token);
+        } else {
+            return Err(invalid(expression, token));
+        }
+    }
+
+    if normalized.contains('~') {
+        return Err(invalid(expression, "~"));
+    }
+
+    let (year, month, day) = match date.map(|date| date.split('-').collect::<Vec<_>>()) {
+        None => ("*".to_owned(), "*".to_owned(), "*".to_owned()),
+        Some(fields) => match fields.as_slice() {
+            [month, day] => ("*".to_owned(), component(month), component(day)),
+            [year, month, day] => (component(year), component(month), component(day)),
+            _ => return Err(invalid(expression, date.unwrap_or_default())),
+        },
+    };
+
+    let (hour, minute, second) = match time.map(|time| time.split(':').collect::<Vec<_>>()) {
+        None => ("0".to_owned(), "0".to_owned(), "0".to_owned()),
+        Some(fields) => match fields.as_slice() {
+            [hour, minute] => (component(hour), component(minute), "0".to_owned()),
+            [hour, minute, second] => (component(hour), component(minute), component(second)),
+            _ => return Err(invalid(expression, time.unwrap_or_default())),
+        },
+    };
+
+    cron::Schedule::from_str(&format!(
+        "{second} {minute} {hour} {day} {month} {weekdays} {year}"
+    ))
+}
+
+/// Converts a component of a calendar expression into its cron equivalent.
+fn component(field: &str) -> String {
+    field.replace("..", "-")
+}
+
+fn invalid(expression: &str, token: &str) -> Error {
+    ErrorKind::Expression(format!(
+        "'{token}' is not supported in the calendar expression '{expression}'"
+    ))
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use chrono::{TimeZone, Utc};
+
+    /// The first `count` times that an expression matches from the start of 2024,
+    /// which began on a Monday.
+    fn upcoming(expression: &str, count: usize) -> Vec<String> {
+        let start = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+        parse(expression)
+            .unwrap()
+            .after(&start)
+            .take(count)
+            .map(|time| time.format("%a %F %T").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn shorthands() {
+        assert_eq!(
+            upcoming("minutely", 2),
+            ["Mon 2024-01-01 00:01:00", "Mon 2024-01-01 00:02:00"]
+        );
+        assert_eq!(
+            upcoming("hourly", 2),
+            ["Mon 2024-01-01 01:00:00", "Mon 2024-01-01 02:00:00"]
+        );
+        assert_eq!(
+            upcoming("daily", 2),
+            ["Tue 2024-01-02 00:00:00", "Wed 2024-01-03 00:00:00"]
+        );
+        assert_eq!(
+            upcoming("weekly", 2),
+            ["Mon 2024-01-08 00:00:00", "Mon 2024-01-15 00:00:00"]
+        );
+        assert_eq!(
+            upcoming("monthly", 2),
+            ["Thu 2024-02-01 00:00:00", "Fri 2024-03-01 00:00:00"]
+        );
+        assert_eq!(
+            upcoming("quarterly", 2),
+            ["Mon 2024-04-01 00:00:00", "Mon 2024-07-01 00:00:00"]
+        );
+        assert_eq!(upcoming("semiannually", 1), ["Mon 2024-07-01 00:00:00"]);
+        assert_eq!(upcoming("yearly", 1), ["Wed 2025-01-01 00:00:00"]);
+        assert_eq!(upcoming("Annually", 1), ["Wed 2025-01-01 00:00:00"]);
+    }
+
+    #[test]
+    fn weekday_lists_and_ranges() {
+        assert_eq!(
+            upcoming("Mon..Fri 22:00", 6),
+            [
+                "Mon 2024-01-01 22:00:00",
+                "Tue 2024-01-02 22:00:00",
+                "Wed 2024-01-03 22:00:00",
+                "Thu 2024-01-04 22:00:00",
+                "Fri 2024-01-05 22:00:00",
+                "Mon 2024-01-08 22:00:00",
+            ]
+        );
+
+        assert_eq!(
+            upcoming("Tue,Sat 08:30", 3),
+            [
+                "Tue 2024-01-02 08:30:00",
+                "Sat 2024-01-06 08:30:00",
+                "Tue 2024-01-09 08:30:00"
+            ]
+        );
+
+        assert_eq!(
+            upcoming("Sat,Sun..Mon 12:00", 4),
+            [
+                "Mon 2024-01-01 12:00:00",
+                "Sat 2024-01-06 12:00:00",
+                "Sun 2024-01-07 12:00:00",
+                "Mon 2024-01-08 12:00:00",
+            ]
+        );
+    }
+
+    #[test]
+    fn dates_and_times() {
+        assert_eq!(
+            upcoming("2024-03-15 06:45:30", 1),
+            ["Fri 2024-03-15 06:45:30"]
+        );
+        assert_eq!(
+            upcoming("*-02-29 00:00", 2),
+            ["Thu 2024-02-29 00:00:00", "Tue 2028-02-29 00:00:00"]
+        );
+        assert_eq!(upcoming("12-25", 1), ["Wed 2024-12-25 00:00:00"]);
+        assert_eq!(upcoming(" daily ", 1), ["Tue 2024-01-02 00:00:00"]);
+    }
+
+    #[test]
+    fn repetitions() {
+        assert_eq!(
+            upcoming("*:0/15", 5),
+            [
+                "Mon 2024-01-01 00:15:00",
+                "Mon 2024-01-01 00:30:00",
+                "Mon 2024-01-01 00:45:00",
+                "Mon 2024-01-01 01:00:00",
+                "Mon 2024-01-01 01:15:00",
+            ]
+        );
+
+        assert_eq!(
+            upcoming("*-*-* 0/6:00", 3),
+            [
+                "Mon 2024-01-01 06:00:00",
+                "Mon 2024-01-01 12:00:00",
+                "Mon 2024-01-01 18:00:00"
+            ]
+        );
+
+        assert_eq!(
+            upcoming("*-*-1/10 00:00", 3),
+            [
+                "Thu 2024-01-11 00:00:00",
+                "Sun 2024-01-21 00:00:00",
+                "Wed 2024-01-31 00:00:00",
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_expressions_are_rejected() {
+        for expression in [
+            "",
+            "someday",
+            "Mon 22:00 Tue",
+            "22:00 2024-01-01",
+            "2024-01-01-01 00:00",
+            "1:2:3:4",
+            "*-*~01",
+            "Mon 25:00",
+            "Mon 22:60",
+            "22:00:60",
+            "*-13-01",
+            "*-01-32",
+            "Funday 22:00",
+        ] {
+            assert!(
+                parse(expression).is_err(),
+                "'{expression}' should be rejected"
+            );
+        }
+    }
+}
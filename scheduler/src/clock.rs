@@ -0,0 +1,193 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::TimeZoneExt;
+use chrono::DateTime;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// A source of the current time for the scheduler service.
+pub trait Clock<Tz: TimeZoneExt>: Send + Sync + 'static {
+    /// Get the current time in this timezone.
+    fn now(&self) -> DateTime<Tz>;
+}
+
+/// The clock of the system, as given by [`TimeZoneExt::now`].
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SystemClock;
+
+impl<Tz: TimeZoneExt> Clock<Tz> for SystemClock {
+    fn now(&self) -> DateTime<Tz> {
+        Tz::now()
+    }
+}
+
+/// A clock whose time only moves when the caller says so.
+///
+/// The wall clock and the monotonic clock that timers are based on are tracked
+/// separately, which makes it possible to simulate a suspended system or a
+/// change to the system time.
+///
+/// ```
+/// use async_cron_scheduler::{Clock, VirtualClock};
+/// use chrono::{TimeZone, Utc};
+///
+/// let clock = VirtualClock::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+///
+/// clock.advance(chrono::Duration::hours(1));
+/// assert_eq!(clock.now(), Utc.with_ymd_and_hms(2022, 1, 1, 1, 0, 0).unwrap());
+/// ```
+pub struct VirtualClock<Tz: TimeZoneExt> {
+    state: Arc<Mutex<VirtualState<Tz>>>,
+}
+
+struct VirtualState<Tz: TimeZoneExt> {
+    now: DateTime<Tz>,
+    elapsed: Duration,
+    timers: Vec<VirtualTimer>,
+    next_timer: u64,
+}
+
+struct VirtualTimer {
+    id: u64,
+    deadline: Duration,
+    waker: Option<Waker>,
+}
+
+impl<Tz: TimeZoneExt> Clone for VirtualClock<Tz> {
+    fn clone(&self) -> Self {
+        Self {
+            state: self.state.clone(),
+        }
+    }
+}
+
+impl<Tz: TimeZoneExt + 'static> VirtualClock<Tz>
+where
+    Tz::Offset: Send + Sync,
+{
+    /// Creates a virtual clock which starts at the given time.
+    #[must_use]
+    pub fn new(start: DateTime<Tz>) -> Self {
+        Self {
+            state: Arc::new(Mutex::new(VirtualState {
+                now: start,
+                elapsed: Duration::ZERO,
+                timers: Vec::new(),
+                next_timer: 0,
+            })),
+        }
+    }
+
+    /// Moves time forward, waking any timers that have elapsed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the duration is negative.
+    pub fn advance(&self, duration: chrono::Duration) {
+        let monotonic = duration.to_std().expect("time cannot go backwards");
+
+        let mut state = self.state.lock().unwrap();
+        state.now = state.now.clone() + duration;
+        state.elapsed += monotonic;
+
+        let elapsed = state.elapsed;
+        for timer in &mut state.timers {
+            if timer.deadline <= elapsed {
+                if let Some(waker) = timer.waker.take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+
+    /// Changes the wall clock without moving the monotonic clock that timers are based on.
+    ///
+    /// This is what happens when the system resumes from suspend, or when the
+    /// time is changed by NTP or the administrator.
+    pub fn set(&self, now: DateTime<Tz>) {
+        self.state.lock().unwrap().now = now;
+    }
+
+    /// A timer function for the scheduler service which sleeps on this clock.
+    pub fn timer(&self) -> impl Fn(Duration) -> Sleep<Tz> + Send + Sync + 'static {
+        let clock = self.clone();
+        move |duration| clock.sleep(duration)
+    }
+
+    /// Creates a future which completes after the duration has elapsed on this clock.
+    #[must_use]
+    pub fn sleep(&self, duration: Duration) -> Sleep<Tz> {
+        let mut state = self.state.lock().unwrap();
+        let id = state.next_timer;
+        let deadline = state.elapsed + duration;
+        state.next_timer += 1;
+        state.timers.push(VirtualTimer {
+            id,
+            deadline,
+            waker: None,
+        });
+
+        Sleep {
+            clock: self.clone(),
+            id,
+            deadline,
+        }
+    }
+
+    /// Time remaining until the earliest pending timer elapses.
+    #[must_use]
+    pub fn next_timer(&self) -> Option<Duration> {
+        let state = self.state.lock().unwrap();
+        state
+            .timers
+            .iter()
+            .map(|timer| timer.deadline.saturating_sub(state.elapsed))
+            .min()
+    }
+}
+
+impl<Tz: TimeZoneExt + 'static> Clock<Tz> for VirtualClock<Tz>
+where
+    Tz::Offset: Send + Sync,
+{
+    fn now(&self) -> DateTime<Tz> {
+        self.state.lock().unwrap().now.clone()
+    }
+}
+
+/// A future which completes when its deadline on a [`VirtualClock`] has elapsed.
+pub struct Sleep<Tz: TimeZoneExt> {
+    clock: VirtualClock<Tz>,
+    id: u64,
+    deadline: Duration,
+}
+
+impl<Tz: TimeZoneExt> Future for Sleep<Tz> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = self.clock.state.lock().unwrap();
+
+        if state.elapsed >= self.deadline {
+            return Poll::Ready(());
+        }
+
+        if let Some(timer) = state.timers.iter_mut().find(|timer| timer.id == self.id) {
+            timer.waker = Some(cx.waker().clone());
+        }
+
+        Poll::Pending
+    }
+}
+
+impl<Tz: TimeZoneExt> Drop for Sleep<Tz> {
+    fn drop(&mut self) {
+        if let Ok(mut state) = self.clock.state.lock() {
+            state.timers.retain(|timer| timer.id != self.id);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+use crate::{Job, JobId, Scheduler, TimeZoneExt, VirtualClock};
+use chrono::DateTime;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+/// Drives a scheduler service on a [`VirtualClock`] for deterministic testing.
+///
+/// The service is polled in place without an async runtime, and time only moves
+/// when the harness is advanced. Every job inserted through the harness records
+/// its ID when it fires, so that tests can assert which jobs fired in what order.
+///
+/// ```
+/// use async_cron_scheduler::{Harness, Job};
+/// use chrono::{Duration, TimeZone, Utc};
+///
+/// let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+///
+/// let fizz = harness.insert(Job::cron("0 */2 * * * *").unwrap());
+/// let buzz = harness.insert(Job::cron("0 */3 * * * *").unwrap());
+///
+/// harness.advance(Duration::minutes(6));
+/// assert_eq!(harness.fired(), vec![fizz, buzz, fizz, fizz, buzz]);
+///
+/// harness.scheduler.remove(fizz);
+/// harness.advance(Duration::minutes(6));
+/// assert_eq!(harness.fired(), vec![buzz, buzz]);
+/// ```
+///
+/// Suspending the system stops the monotonic clock while the wall clock moves on.
+///
+/// ```
+/// use async_cron_scheduler::{Harness, Job, MemoryStore};
+/// use chrono::{TimeZone, Utc};
+///
+/// let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 21, 0, 0).unwrap());
+///
+/// let job = Job::cron("0 0 22 * * *").unwrap().catch_up(MemoryStore::default());
+/// let update = harness.insert(job);
+///
+/// // Suspended at 21:00, and resumed the next morning.
+/// harness.set(Utc.with_ymd_and_hms(2022, 1, 2, 9, 0, 0).unwrap());
+/// harness.scheduler.resync();
+///
+/// assert_eq!(harness.fired(), vec![update]);
+/// ```
+pub struct Harness<Tz: TimeZoneExt> {
+    /// The scheduler attached to the service being driven.
+    pub scheduler: Scheduler<Tz>,
+
+    /// The clock that the service is running on.
+    pub clock: VirtualClock<Tz>,
+
+    fired: Arc<Mutex<Vec<JobId>>>,
+    service: Pin<Box<dyn Future<Output = ()> + Send + Sync>>,
+}
+
+impl<Tz: TimeZoneExt + 'static> Harness<Tz>
+where
+    Tz::Offset: Send + Sync,
+{
+    /// Launches a scheduler service on a virtual clock starting at the given time.
+    #[must_use]
+    pub fn new(start: DateTime<Tz>) -> Self {
+        let clock = VirtualClock::new(start);
+        let (scheduler, service) = Scheduler::launch_with_clock(clock.clone(), clock.timer());
+
+        let mut harness = Self {
+            scheduler,
+            clock,
+            fired: Arc::default(),
+            service: Box::pin(service),
+        };
+
+        harness.poll();
+        harness
+    }
+
+    /// Inserts a job which records its ID when it fires.
+    pub fn insert(&mut self, job: Job<Tz>) -> JobId {
+        let fired = self.fired.clone();
+        let id = self
+            .scheduler
+            .insert(job, move |id| fired.lock().unwrap().push(id));
+
+        self.poll();
+        id
+    }
+
+    /// Inserts an async job which records its ID when it fires, and runs for the
+    /// given duration on the virtual clock.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the duration is negative.
+    pub fn insert_async(&mut self, job: Job<Tz>, duration: chrono::Duration) -> JobId {
+        let duration = duration.to_std().expect("time cannot go backwards");
+        let fired = self.fired.clone();
+        let clock = self.clock.clone();
+        let id = self.scheduler.insert_async(job, move |id| {
+            fired.lock().unwrap().push(id);
+            clock.sleep(duration)
+        });
+
+        self.poll();
+        id
+    }
+
+    /// Moves time forward, stopping at each timer along the way to let the service react.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the duration is negative.
+    pub fn advance(&mut self, duration: chrono::Duration) {
+        let mut remaining = duration.to_std().expect("time cannot go backwards");
+
+        self.poll();
+
+        while let Some(next) = self.clock.next_timer() {
+            if next > remaining {
+                break;
+            }
+
+            self.clock
+                .advance(chrono::Duration::from_std(next).unwrap());
+            remaining -= next;
+            self.poll();
+        }
+
+        self.clock
+            .advance(chrono::Duration::from_std(remaining).unwrap());
+        self.poll();
+    }
+
+    /// Changes the wall clock without moving the monotonic clock, as if the system
+    /// resumed from suspend or the time was changed.
+    pub fn set(&mut self, now: DateTime<Tz>) {
+        self.clock.set(now);
+        self.poll();
+    }
+
+    /// Takes the IDs of the jobs that have fired since the last call, in the order they fired.
+    #[must_use]
+    pub fn fired(&mut self) -> Vec<JobId> {
+        self.poll();
+        std::mem::take(&mut *self.fired.lock().unwrap())
+    }
+
+    /// Runs a query against the scheduler, polling the service until it is answered.
+    ///
+    /// ```
+    /// # use async_cron_scheduler::{Harness, Job};
+    /// # use chrono::{TimeZone, Utc};
+    /// # let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let fizz_id = harness.insert(Job::cron("0 * * * * *").unwrap());
+    /// assert_eq!(harness.query(|scheduler| scheduler.jobs()), vec![fizz_id]);
+    /// ```
+    pub fn query<'a, F: Future + 'a>(
+        &'a mut self,
+        query: impl FnOnce(&'a Scheduler<Tz>) -> F,
+    ) -> F::Output {
+        let waker = futures::task::noop_waker();
+        let mut context = Context::from_waker(&waker);
+
+        let future = query(&self.scheduler);
+        futures::pin_mut!(future);
+
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+
+            let _ = self.service.as_mut().poll(&mut context);
+        }
+    }
+
+    /// Polls the service until it is waiting on a message or a timer.
+    pub fn poll(&mut self) {
+        let waker = futures::task::noop_waker();
+        let _ = self.service.as_mut().poll(&mut Context::from_waker(&waker));
+    }
+}
@@ -2,8 +2,10 @@
 // SPDX-License-Identifier: MPL-2.0
 
 use crate::TimeZoneExt;
-use chrono::DateTime;
+use chrono::{DateTime, Utc};
 use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 /// The ID of a scheduled job.
 #[allow(clippy::module_name_repetitions)]
@@ -12,8 +14,47 @@ pub struct JobId(pub slotmap::DefaultKey);
 
 /// Contains scheduling information for a job at a given timezone.
 pub struct Job<Tz: TimeZoneExt> {
-    pub(crate) iterator: cron::OwnedScheduleIterator<Tz>,
+    pub(crate) schedule: Recurrence<Tz>,
     pub(crate) next: DateTime<Tz>,
+    pub(crate) occurrence: DateTime<Tz>,
+    pub(crate) store: Option<Box<dyn RunStore>>,
+    pub(crate) window: Option<Duration>,
+    pub(crate) deadline: Option<Duration>,
+    pub(crate) overlap: Overlap,
+}
+
+/// What to do when an async job is due while a previous run of it is still running.
+///
+/// ```
+/// use async_cron_scheduler::{Harness, Job, Overlap};
+/// use chrono::{Duration, TimeZone, Utc};
+///
+/// let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+///
+/// // Due every minute, but takes 90 seconds: runs start at 1:00, 2:30, 4:00, and 5:30.
+/// let job = Job::cron("0 * * * * *").unwrap().overlap(Overlap::Queue);
+/// let queued = harness.insert_async(job, Duration::seconds(90));
+///
+/// harness.advance(Duration::minutes(6));
+/// assert_eq!(harness.fired().len(), 4);
+///
+/// // Parallel runs start every minute regardless.
+/// harness.scheduler.remove(queued);
+/// let job = Job::cron("0 * * * * *").unwrap().overlap(Overlap::Parallel);
+/// harness.insert_async(job, Duration::seconds(90));
+///
+/// harness.advance(Duration::minutes(6));
+/// assert_eq!(harness.fired().len(), 6);
+/// ```
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Overlap {
+    /// Start another run alongside the ones still running.
+    #[default]
+    Parallel,
+    /// Run once more after the current run completes, if it was due in the meantime.
+    Queue,
+    /// Skip the occurrence.
+    Skip,
 }
 
 impl<Tz: TimeZoneExt> Job<Tz> {
@@ -27,15 +68,374 @@ impl<Tz: TimeZoneExt> Job<Tz> {
     }
 
     /// Creates a job from a pre-generated cron schedule.
+    #[must_use]
+    pub fn cron_schedule(schedule: cron::Schedule) -> Self {
+        Job::new(Recurrence::Cron(vec![schedule]))
+    }
+
+    /// Creates a job which occurs whenever any of the cron expressions do.
+    ///
+    /// ```
+    /// use async_cron_scheduler::{Harness, Job};
+    /// use chrono::{Duration, TimeZone, Utc};
+    ///
+    /// let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let job = Job::crons(&["0 30 6 * * *", "0 0 22 * * *"]).unwrap();
+    /// let update = harness.insert(job);
+    ///
+    /// harness.advance(Duration::days(1));
+    /// assert_eq!(harness.fired(), vec![update, update]);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Errors if any of the cron expressions are invalid.
+    pub fn crons<S: AsRef<str>>(expressions: &[S]) -> Result<Self, cron::error::Error> {
+        expressions
+            .iter()
+            .map(|expression| cron::Schedule::from_str(expression.as_ref()))
+            .collect::<Result<Vec<_>, _>>()
+            .map(|schedules| Job::new(Recurrence::Cron(schedules)))
+    }
+
+    /// Creates a job from a systemd calendar event expression, such as `Mon..Fri 22:00`.
+    ///
+    /// ```
+    /// use async_cron_scheduler::{Harness, Job};
+    /// use chrono::{TimeZone, Utc};
+    ///
+    /// // 2022-01-01 is a Saturday.
+    /// let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let update = harness.insert(Job::calendar("Mon..Fri 22:00").unwrap());
+    ///
+    /// let next = harness.query(|scheduler| scheduler.next_occurrence(update));
+    /// assert_eq!(next, Some(Utc.with_ymd_and_hms(2022, 1, 3, 22, 0, 0).unwrap()));
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Errors if the expression is invalid, or uses a feature that is not supported,
+    /// such as time zones.
+    pub fn calendar(expression: &str) -> Result<Self, cron::error::Error> {
+        crate::calendar::parse(expression).map(Job::cron_schedule)
+    }
+
+    /// Creates a job which repeats at a fixed interval after it was started.
+    ///
+    /// ```
+    /// use async_cron_scheduler::{Harness, Job};
+    /// use chrono::{Duration, TimeZone, Utc};
+    ///
+    /// let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let check = harness.insert(Job::interval(std::time::Duration::from_secs(12 * 60 * 60)));
+    ///
+    /// harness.advance(Duration::days(2));
+    /// assert_eq!(harness.fired(), vec![check; 4]);
+    /// ```
     ///
     /// # Panics
     ///
-    /// Panics at the end of time.
+    /// Panics if the interval is zero, or too long to be represented.
     #[must_use]
-    #[allow(clippy::needless_pass_by_value)]
-    pub fn cron_schedule(schedule: cron::Schedule) -> Self {
-        let mut iterator = schedule.upcoming_owned(Tz::timescale());
-        let next = iterator.next().unwrap();
-        Job { iterator, next }
+    pub fn interval(interval: Duration) -> Self {
+        assert!(!interval.is_zero(), "the interval of a job cannot be zero");
+        let interval = chrono::Duration::from_std(interval).expect("interval is too long");
+        Job::new(Recurrence::Interval(interval))
+    }
+
+    /// Creates a job which is executed once at the given time.
+    ///
+    /// If the time has already passed, the job is never executed.
+    ///
+    /// ```
+    /// use async_cron_scheduler::{Harness, Job};
+    /// use chrono::{Duration, TimeZone, Utc};
+    ///
+    /// let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let job = Job::once(Utc.with_ymd_and_hms(2022, 1, 1, 0, 1, 0).unwrap());
+    /// let reminder = harness.insert(job);
+    ///
+    /// harness.advance(Duration::hours(1));
+    /// assert_eq!(harness.fired(), vec![reminder]);
+    /// assert!(harness.query(|scheduler| scheduler.jobs()).is_empty());
+    /// ```
+    #[must_use]
+    pub fn once(at: DateTime<Tz>) -> Self {
+        Job::new(Recurrence::Once(at))
+    }
+
+    /// The first occurrence is found when the job is started by the scheduler service.
+    fn new(schedule: Recurrence<Tz>) -> Self {
+        let now = Tz::now();
+        Job {
+            schedule,
+            occurrence: now.clone(),
+            next: now,
+            store: None,
+            window: None,
+            deadline: None,
+            overlap: Overlap::default(),
+        }
+    }
+
+    /// Start the job at a random time within a window after each occurrence.
+    ///
+    /// This spreads out the load of many systems sharing the same schedule. If
+    /// the job could not start before the window closed, such as when the system
+    /// was suspended, that occurrence is skipped, unless a longer [`Job::deadline`]
+    /// was given.
+    ///
+    /// ```
+    /// use async_cron_scheduler::Job;
+    /// use chrono::offset::Local;
+    /// use std::time::Duration;
+    ///
+    /// // Anytime between 22:00 and 02:00 on weekdays.
+    /// let job = Job::<Local>::cron("0 0 22 * * 1-5")
+    ///     .unwrap()
+    ///     .window(Duration::from_secs(4 * 60 * 60));
+    /// ```
+    #[must_use]
+    pub fn window(mut self, window: Duration) -> Self {
+        self.window = Some(window);
+        self
+    }
+
+    /// Skip an occurrence of the job if it could not start within this duration of its scheduled time.
+    ///
+    /// ```
+    /// use async_cron_scheduler::{Harness, Job};
+    /// use chrono::{TimeZone, Utc};
+    /// use std::time::Duration;
+    ///
+    /// let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 21, 0, 0).unwrap());
+    ///
+    /// let job = Job::cron("0 0 22 * * *")
+    ///     .unwrap()
+    ///     .deadline(Duration::from_secs(60 * 60));
+    ///
+    /// harness.insert(job);
+    ///
+    /// // Suspended at 21:00, and resumed the next morning.
+    /// harness.set(Utc.with_ymd_and_hms(2022, 1, 2, 9, 0, 0).unwrap());
+    /// harness.scheduler.resync();
+    ///
+    /// assert!(harness.fired().is_empty());
+    /// ```
+    #[must_use]
+    pub fn deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    /// What to do when the job is due while a previous run is still running.
+    ///
+    /// This only applies to jobs inserted with [`Scheduler::insert_async`], since
+    /// synchronous commands have completed by the time the next run is due.
+    ///
+    /// ```
+    /// use async_cron_scheduler::{Harness, Job, Overlap};
+    /// use chrono::{Duration, TimeZone, Utc};
+    ///
+    /// let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    ///
+    /// // A job which is due every minute, but takes 90 seconds to complete.
+    /// let job = Job::cron("0 * * * * *").unwrap().overlap(Overlap::Skip);
+    /// let update = harness.insert_async(job, Duration::seconds(90));
+    ///
+    /// harness.advance(Duration::minutes(6));
+    /// assert_eq!(harness.fired(), vec![update, update, update]);
+    /// ```
+    ///
+    /// [`Scheduler::insert_async`]: crate::Scheduler::insert_async
+    #[must_use]
+    pub fn overlap(mut self, overlap: Overlap) -> Self {
+        self.overlap = overlap;
+        self
+    }
+
+    /// Catch up on a missed run, using the store to persist the time of the last run.
+    ///
+    /// If an occurrence of the job was missed since the last recorded run, such as
+    /// when the system was suspended or powered off, the job will be executed once
+    /// when it is inserted into the scheduler, or when the scheduler wakes up late.
+    /// Any other missed occurrences are skipped, as is a missed occurrence whose
+    /// window or deadline has passed.
+    ///
+    /// The scheduler only reads the store. The command of the job records its run
+    /// once it has succeeded, so that a run which failed is caught up on as well.
+    ///
+    /// ```
+    /// use async_cron_scheduler::{Harness, Job, MemoryStore, RunStore};
+    /// use chrono::{TimeZone, Utc};
+    /// use std::sync::Arc;
+    ///
+    /// let store = Arc::new(MemoryStore::new(Utc.with_ymd_and_hms(2021, 12, 31, 22, 0, 0).unwrap()));
+    /// let job = || Job::cron("0 0 22 * * *").unwrap().catch_up(store.clone());
+    ///
+    /// // Started after the run at 22:00 was missed.
+    /// let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 23, 0, 0).unwrap());
+    /// let update = harness.insert(job());
+    /// assert_eq!(harness.fired(), vec![update]);
+    ///
+    /// // The run failed, so it was not recorded, and is caught up on again after a restart.
+    /// harness.scheduler.remove(update);
+    /// let update = harness.insert(job());
+    /// assert_eq!(harness.fired(), vec![update]);
+    ///
+    /// // Once a run succeeds, it is recorded by the command.
+    /// store.set_last_run(Utc.with_ymd_and_hms(2022, 1, 1, 23, 0, 0).unwrap());
+    /// harness.scheduler.remove(update);
+    /// harness.insert(job());
+    /// assert!(harness.fired().is_empty());
+    /// ```
+    #[must_use]
+    pub fn catch_up(mut self, store: impl RunStore + 'static) -> Self {
+        self.store = Some(Box::new(store));
+        self
+    }
+
+    /// Whether an occurrence was missed since the last recorded run.
+    pub(crate) fn missed_run(&self, now: &DateTime<Tz>) -> bool {
+        let last_run = match self.store.as_ref().and_then(|store| store.last_run()) {
+            Some(last_run) => last_run.with_timezone(&Tz::timescale()),
+            None => return false,
+        };
+
+        match self.schedule.after(&last_run) {
+            Some(missed) => missed <= *now && !self.past_deadline(&missed, now),
+            None => false,
+        }
+    }
+
+    /// Whether the current occurrence can no longer start inside its window or deadline.
+    pub(crate) fn expired(&self, now: &DateTime<Tz>) -> bool {
+        self.past_deadline(&self.occurrence, now)
+    }
+
+    fn past_deadline(&self, occurrence: &DateTime<Tz>, now: &DateTime<Tz>) -> bool {
+        let latest = self
+            .deadline
+            .or(self.window)
+            .and_then(|deadline| chrono::Duration::from_std(deadline).ok())
+            .and_then(|deadline| occurrence.clone().checked_add_signed(deadline));
+
+        matches!(latest, Some(latest) if *now > latest)
+    }
+
+    /// Moves on to the next occurrence, if there is one.
+    pub(crate) fn advance(&mut self) -> bool {
+        let occurrence = self.occurrence.clone();
+        self.advance_from(&occurrence)
+    }
+
+    /// Moves on to the first occurrence after the given time, if there is one.
+    fn advance_from(&mut self, time: &DateTime<Tz>) -> bool {
+        let occurrence = match self.schedule.after(time) {
+            Some(occurrence) => occurrence,
+            None => return false,
+        };
+
+        self.next = occurrence.clone() + self.jitter();
+        self.occurrence = occurrence;
+        true
+    }
+
+    /// Skips every occurrence before the given time, if there is one after it.
+    pub(crate) fn skip_until(&mut self, until: &DateTime<Tz>) -> bool {
+        while self.occurrence < *until {
+            if !self.advance() {
+                return false;
+            }
+        }
+
+        true
+    }
+
+    /// Counts the remaining occurrences, including the current one, up to a limit.
+    pub(crate) fn remaining(&self, limit: usize) -> usize {
+        std::iter::successors(Some(self.occurrence.clone()), |time| {
+            self.schedule.after(time)
+        })
+        .take(limit)
+        .count()
+    }
+
+    /// A random delay within the window of the job.
+    fn jitter(&self) -> chrono::Duration {
+        self.window
+            .map(|window| Duration::from_secs(fastrand::u64(0..=window.as_secs())))
+            .and_then(|delay| chrono::Duration::from_std(delay).ok())
+            .unwrap_or_else(chrono::Duration::zero)
+    }
+
+    /// Schedules the next occurrence after the current time, if there is one.
+    pub(crate) fn start(&mut self, now: &DateTime<Tz>) -> bool {
+        self.advance_from(now)
+    }
+}
+
+/// The rule that the occurrences of a job follow.
+pub(crate) enum Recurrence<Tz: TimeZoneExt> {
+    Cron(Vec<cron::Schedule>),
+    Interval(chrono::Duration),
+    Once(DateTime<Tz>),
+}
+
+impl<Tz: TimeZoneExt> Recurrence<Tz> {
+    /// The first occurrence after the given time.
+    ///
+    /// Intervals are counted from the given time, which is either when the job
+    /// was started, when it last ran, or its previous occurrence.
+    fn after(&self, time: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        match self {
+            Recurrence::Cron(schedules) => schedules
+                .iter()
+                .filter_map(|schedule| schedule.after(time).next())
+                .min(),
+            Recurrence::Interval(interval) => time.clone().checked_add_signed(*interval),
+            Recurrence::Once(at) => Some(at.clone()).filter(|at| at > time),
+        }
+    }
+}
+
+/// Persists the time that a job last ran, to be able to catch up on missed runs.
+pub trait RunStore: Send + Sync {
+    /// The time that the job last ran, if it has ever been recorded.
+    fn last_run(&self) -> Option<DateTime<Utc>>;
+
+    /// Records the time that the job last ran.
+    fn set_last_run(&self, time: DateTime<Utc>);
+}
+
+impl<T: RunStore + ?Sized> RunStore for Arc<T> {
+    fn last_run(&self) -> Option<DateTime<Utc>> {
+        (**self).last_run()
+    }
+
+    fn set_last_run(&self, time: DateTime<Utc>) {
+        (**self).set_last_run(time);
+    }
+}
+
+/// A run store that is kept in memory, and therefore lost when the process exits.
+#[derive(Debug, Default)]
+pub struct MemoryStore(Mutex<Option<DateTime<Utc>>>);
+
+impl MemoryStore {
+    /// Creates a store with a previously-recorded last run.
+    #[must_use]
+    pub fn new(last_run: DateTime<Utc>) -> Self {
+        Self(Mutex::new(Some(last_run)))
+    }
+}
+
+impl RunStore for MemoryStore {
+    fn last_run(&self) -> Option<DateTime<Utc>> {
+        *self.0.lock().unwrap()
+    }
+
+    fn set_last_run(&self, time: DateTime<Utc>) {
+        *self.0.lock().unwrap() = Some(time);
     }
 }
@@ -12,14 +12,20 @@
 //! - **Async**: A single future drives the entire scheduler service.
 //! - **Task Scheduling**: Schedule multiple jobs with varying timeframes between them.
 //! - **Cron Expressions**: Standardized format for scheduling syntax.
+//! - **Other Schedules**: Fixed intervals, one-shot times, and systemd calendar events.
+//! - **Missed Runs**: Optionally catch up on runs missed while the system was asleep.
+//! - **Time Windows**: Spread jobs randomly across a window, and skip them if it passed.
+//! - **Testable**: Drive the scheduler on a virtual clock to test jobs without sleeping.
+//! - **Async Jobs**: Jobs may return futures, with a policy for when runs overlap.
 //!
 //! # Tips
 //!
 //! Scheduled jobs block the executor when they are executing, so it's best to keep
 //! their execution short. It's recommended practice to either spawn tasks onto an
-//! executor, or send messages from a channel. The good news is that each job being
-//! executed has a unique ID associated with it, which you can use for tracking
-//! specific tasks.
+//! executor, or send messages from a channel. Jobs inserted with
+//! [`Scheduler::insert_async`] return a future which is driven by the scheduler
+//! service instead. The good news is that each job being executed has a unique ID
+//! associated with it, which you can use for tracking specific tasks.
 //!
 //! # Demo
 //!
@@ -73,9 +79,14 @@ use chrono::DateTime;
 use chrono::TimeZone;
 pub use cron;
 
+mod calendar;
+mod clock;
+mod harness;
 mod job;
 mod scheduler;
 
+pub use self::clock::*;
+pub use self::harness::*;
 pub use self::job::*;
 pub use self::scheduler::*;
 
@@ -1,20 +1,42 @@
 // Copyright 2021-2022 System76 <info@system76.com>
 // SPDX-License-Identifier: MPL-2.0
 
-use crate::{Job, JobId, TimeZoneExt};
+use crate::{Clock, Job, JobId, Overlap, SystemClock, TimeZoneExt};
 use chrono::DateTime;
 use flume::{RecvError, Sender};
 use futures::future::Either;
+use futures::stream::{FuturesUnordered, StreamExt};
 use slotmap::{DefaultKey, SecondaryMap, SlotMap};
-use std::{future::Future, time::Duration};
+use std::{future::Future, pin::Pin, time::Duration};
 
 /// A scheduled command associated with a job.
 pub type Command = Box<dyn Fn(JobId) + Send + Sync>;
 
+/// A scheduled command associated with a job, which runs until its future completes.
+pub type AsyncCommand = Box<dyn Fn(JobId) -> RunFuture + Send + Sync>;
+
+/// A run of an async job, which is driven by the scheduler service.
+pub type RunFuture = Pin<Box<dyn Future<Output = ()> + Send + Sync>>;
+
+/// The longest that the service will sleep before checking the time again.
+///
+/// Timers are based on a monotonic clock, which may be paused while the system is
+/// suspended, or drift from the wall clock after NTP corrections and DST changes.
+const REVALIDATE_INTERVAL: Duration = Duration::from_secs(60);
+
 /// Messages going into the scheduler service.
 enum SchedMessage<Tz: TimeZoneExt> {
-    Insert(JobId, Job<Tz>, Command),
+    Insert(JobId, Box<Job<Tz>>, Task),
     Remove(JobId),
+    Resync,
+    Pause(JobId, Option<DateTime<Tz>>),
+    Resume(JobId),
+    SkipNext(JobId),
+    Postpone(JobId, DateTime<Tz>),
+    Trigger(JobId),
+    Jobs(Sender<Vec<JobId>>),
+    NextOccurrence(JobId, Sender<Option<DateTime<Tz>>>),
+    Remaining(JobId, usize, Sender<Option<usize>>),
 }
 
 /// The interface for interacting with the scheduler.
@@ -24,10 +46,12 @@ enum SchedMessage<Tz: TimeZoneExt> {
 /// messages are sent to the service for automatic management. When the
 /// scheduler is dropped, so too will the service its attached to exit.
 ///
-/// ```
+/// ```no_run
+/// use async_cron_scheduler::{Job, Scheduler};
 /// use smol::Timer;
 /// use chrono::offset::Local;
 ///
+/// # smol::block_on(async {
 /// let (mut scheduler, service) = Scheduler::<Local>::launch(Timer::after);
 ///
 /// // Creates a job which executes every 3 seconds.
@@ -39,6 +63,7 @@ enum SchedMessage<Tz: TimeZoneExt> {
 /// let buzz_id = scheduler.insert(job, |id| println!("Buzz"));
 ///
 /// service.await;
+/// # });
 /// ```
 pub struct Scheduler<Tz: TimeZoneExt> {
     jobs: SlotMap<DefaultKey, ()>,
@@ -52,6 +77,9 @@ where
     /// Insert a job into the scheduler with the command to call when scheduled.
     ///
     /// ```
+    /// # use async_cron_scheduler::{Job, Scheduler};
+    /// # use chrono::offset::Local;
+    /// # let (mut scheduler, _service) = Scheduler::<Local>::launch(smol::Timer::after);
     /// // Creates a job which executes every 3 seconds.
     /// let job = Job::cron("1/3 * * * * *").unwrap();
     /// let fizz_id = scheduler.insert(job, |id| println!("Fizz"));
@@ -61,16 +89,60 @@ where
         job: Job<Tz>,
         command: impl Fn(JobId) + Send + Sync + 'static,
     ) -> JobId {
+        self.insert_task(job, Task::Sync(Box::new(command)))
+    }
+
+    /// Insert a job into the scheduler with an async command to run when scheduled.
+    ///
+    /// The future returned by the command is driven by the scheduler service, so
+    /// no runtime is needed to spawn it. The service keeps track of when each run
+    /// completes, and applies the job's [`Overlap`] policy when the job is due
+    /// again before the previous run has completed.
+    ///
+    /// ```
+    /// # use async_cron_scheduler::{Job, Overlap, Scheduler};
+    /// # use chrono::offset::Local;
+    /// # let (mut scheduler, _service) = Scheduler::<Local>::launch(smol::Timer::after);
+    /// let (sender, receiver) = flume::bounded(1);
+    ///
+    /// // Creates a job which sends a message every 3 seconds, unless the last is still pending.
+    /// let job = Job::cron("1/3 * * * * *").unwrap().overlap(Overlap::Skip);
+    /// let fizz_id = scheduler.insert_async(job, move |id| {
+    ///     let sender = sender.clone();
+    ///     async move {
+    ///         let _ = sender.send_async(id).await;
+    ///     }
+    /// });
+    /// ```
+    pub fn insert_async<F>(
+        &mut self,
+        job: Job<Tz>,
+        command: impl Fn(JobId) -> F + Send + Sync + 'static,
+    ) -> JobId
+    where
+        F: Future<Output = ()> + Send + Sync + 'static,
+    {
+        self.insert_task(
+            job,
+            Task::Async(Box::new(move |id| Box::pin(command(id)) as RunFuture)),
+        )
+    }
+
+    fn insert_task(&mut self, job: Job<Tz>, task: Task) -> JobId {
         let id = JobId(self.jobs.insert(()));
         let _result = self
             .sender
-            .send(SchedMessage::Insert(id, job, Box::new(command)));
+            .send(SchedMessage::Insert(id, Box::new(job), task));
         id
     }
 
     /// Remove a scheduled job from the scheduler.
     ///
     /// ```
+    /// # use async_cron_scheduler::{Job, Scheduler};
+    /// # use chrono::offset::Local;
+    /// # let (mut scheduler, _service) = Scheduler::<Local>::launch(smol::Timer::after);
+    /// # let fizz_id = scheduler.insert(Job::cron("1/3 * * * * *").unwrap(), |_| ());
     /// scheduler.remove(fizz_id);
     /// ```
     pub fn remove(&mut self, job: JobId) {
@@ -79,6 +151,179 @@ where
         }
     }
 
+    /// Re-arms the timer of the scheduler service against the current time.
+    ///
+    /// The service checks the time at least once a minute, but this should be
+    /// called when the system resumes from suspend, or the wall clock is changed,
+    /// to react to it immediately.
+    ///
+    /// ```
+    /// # use async_cron_scheduler::Scheduler;
+    /// # use chrono::offset::Local;
+    /// # let (scheduler, _service) = Scheduler::<Local>::launch(smol::Timer::after);
+    /// scheduler.resync();
+    /// ```
+    pub fn resync(&self) {
+        let _res = self.sender.send(SchedMessage::Resync);
+    }
+
+    /// Pauses a job until it is resumed.
+    ///
+    /// A paused job has no next occurrence, and occurrences missed while it was
+    /// paused are not caught up on.
+    ///
+    /// ```
+    /// # use async_cron_scheduler::{Harness, Job};
+    /// # use chrono::{Duration, TimeZone, Utc};
+    /// # let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let fizz_id = harness.insert(Job::cron("0 * * * * *").unwrap());
+    ///
+    /// harness.scheduler.pause(fizz_id);
+    /// harness.advance(Duration::minutes(5));
+    /// assert!(harness.fired().is_empty());
+    ///
+    /// harness.scheduler.resume(fizz_id);
+    /// harness.advance(Duration::minutes(5));
+    /// assert_eq!(harness.fired().len(), 5);
+    /// ```
+    pub fn pause(&self, job: JobId) {
+        let _res = self.sender.send(SchedMessage::Pause(job, None));
+    }
+
+    /// Skips every occurrence of a job before the given time.
+    ///
+    /// ```
+    /// # use async_cron_scheduler::{Harness, Job};
+    /// # use chrono::{TimeZone, Utc};
+    /// # let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let fizz_id = harness.insert(Job::cron("0 0 22 * * *").unwrap());
+    ///
+    /// harness.scheduler.pause_until(fizz_id, Utc.with_ymd_and_hms(2022, 1, 5, 0, 0, 0).unwrap());
+    ///
+    /// let next = harness.query(|scheduler| scheduler.next_occurrence(fizz_id));
+    /// assert_eq!(next, Some(Utc.with_ymd_and_hms(2022, 1, 5, 22, 0, 0).unwrap()));
+    /// ```
+    pub fn pause_until(&self, job: JobId, until: DateTime<Tz>) {
+        let _res = self.sender.send(SchedMessage::Pause(job, Some(until)));
+    }
+
+    /// Resumes a paused job from its next occurrence after the current time.
+    pub fn resume(&self, job: JobId) {
+        let _res = self.sender.send(SchedMessage::Resume(job));
+    }
+
+    /// Skips the next occurrence of a job.
+    ///
+    /// ```
+    /// # use async_cron_scheduler::{Harness, Job};
+    /// # use chrono::{TimeZone, Utc};
+    /// # let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let fizz_id = harness.insert(Job::cron("0 0 22 * * *").unwrap());
+    ///
+    /// harness.scheduler.skip_next(fizz_id);
+    ///
+    /// let next = harness.query(|scheduler| scheduler.next_occurrence(fizz_id));
+    /// assert_eq!(next, Some(Utc.with_ymd_and_hms(2022, 1, 2, 22, 0, 0).unwrap()));
+    /// ```
+    pub fn skip_next(&self, job: JobId) {
+        let _res = self.sender.send(SchedMessage::SkipNext(job));
+    }
+
+    /// Moves the next occurrence of a job to a later time.
+    ///
+    /// The postponed run is not skipped if it is past the window or deadline of
+    /// its occurrence, and the job continues with its next occurrence after it.
+    ///
+    /// ```
+    /// # use async_cron_scheduler::{Harness, Job};
+    /// # use chrono::{Duration, TimeZone, Utc};
+    /// # let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let fizz_id = harness.insert(Job::cron("0 0 22 * * *").unwrap());
+    ///
+    /// harness.scheduler.postpone(fizz_id, Utc.with_ymd_and_hms(2022, 1, 1, 23, 30, 0).unwrap());
+    ///
+    /// harness.advance(Duration::hours(23));
+    /// assert!(harness.fired().is_empty());
+    ///
+    /// harness.advance(Duration::hours(1));
+    /// assert_eq!(harness.fired(), vec![fizz_id]);
+    ///
+    /// let next = harness.query(|scheduler| scheduler.next_occurrence(fizz_id));
+    /// assert_eq!(next, Some(Utc.with_ymd_and_hms(2022, 1, 2, 22, 0, 0).unwrap()));
+    /// ```
+    pub fn postpone(&self, job: JobId, until: DateTime<Tz>) {
+        let _res = self.sender.send(SchedMessage::Postpone(job, until));
+    }
+
+    /// Executes a job immediately, without affecting its schedule.
+    ///
+    /// This honors the overlap policy of the job, but not its pause.
+    ///
+    /// ```
+    /// # use async_cron_scheduler::{Harness, Job};
+    /// # use chrono::{TimeZone, Utc};
+    /// # let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let fizz_id = harness.insert(Job::cron("0 0 22 * * *").unwrap());
+    ///
+    /// harness.scheduler.trigger(fizz_id);
+    /// assert_eq!(harness.fired(), vec![fizz_id]);
+    /// ```
+    pub fn trigger(&self, job: JobId) {
+        let _res = self.sender.send(SchedMessage::Trigger(job));
+    }
+
+    /// The jobs that are currently scheduled by the service.
+    ///
+    /// Jobs which have no more occurrences are no longer scheduled.
+    ///
+    /// ```
+    /// # use async_cron_scheduler::{Harness, Job};
+    /// # use chrono::{TimeZone, Utc};
+    /// # let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let fizz_id = harness.insert(Job::cron("0 * * * * *").unwrap());
+    /// let jobs = harness.query(|scheduler| scheduler.jobs());
+    /// assert_eq!(jobs, vec![fizz_id]);
+    /// ```
+    pub async fn jobs(&self) -> Vec<JobId> {
+        let (reply, response) = flume::bounded(1);
+        let _res = self.sender.send(SchedMessage::Jobs(reply));
+        response.recv_async().await.unwrap_or_default()
+    }
+
+    /// The time that a job is next due to be executed, if it is scheduled and not paused.
+    ///
+    /// ```
+    /// # use async_cron_scheduler::{Harness, Job};
+    /// # use chrono::{TimeZone, Utc};
+    /// # let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let fizz_id = harness.insert(Job::cron("0 0 22 * * *").unwrap());
+    /// let next = harness.query(|scheduler| scheduler.next_occurrence(fizz_id));
+    /// assert_eq!(next, Some(Utc.with_ymd_and_hms(2022, 1, 1, 22, 0, 0).unwrap()));
+    /// ```
+    pub async fn next_occurrence(&self, job: JobId) -> Option<DateTime<Tz>> {
+        let (reply, response) = flume::bounded(1);
+        let _res = self.sender.send(SchedMessage::NextOccurrence(job, reply));
+        response.recv_async().await.ok().flatten()
+    }
+
+    /// Counts the remaining occurrences of a job, up to the given limit.
+    ///
+    /// Most cron expressions repeat forever, so the count saturates at the limit.
+    ///
+    /// ```
+    /// # use async_cron_scheduler::{Harness, Job};
+    /// # use chrono::{TimeZone, Utc};
+    /// # let mut harness = Harness::new(Utc.with_ymd_and_hms(2022, 1, 1, 0, 0, 0).unwrap());
+    /// let fizz_id = harness.insert(Job::cron("0 0 22 1 1 * 2022-2024").unwrap());
+    /// let remaining = harness.query(|scheduler| scheduler.remaining(fizz_id, 100));
+    /// assert_eq!(remaining, Some(3));
+    /// ```
+    pub async fn remaining(&self, job: JobId, limit: usize) -> Option<usize> {
+        let (reply, response) = flume::bounded(1);
+        let _res = self.sender.send(SchedMessage::Remaining(job, limit, reply));
+        response.recv_async().await.ok().flatten()
+    }
+
     /// Initializes the scheduler and its connected service.
     ///
     /// The API is designed to not rely on any async runtimes. This is achieved by
@@ -90,13 +335,15 @@ where
     /// ## Smol runtime
     ///
     /// ```
+    /// # use async_cron_scheduler::Scheduler;
+    /// # use chrono::offset::Local;
     /// let (mut scheduler, sched_service) = Scheduler::<Local>::launch(smol::Timer::after);
     /// smol::spawn(sched_service).detach();
     /// ```
     ///
     /// ## Tokio runtime
     ///
-    /// ```
+    /// ```ignore
     /// let (mut scheduler, sched_service) = Scheduler::<Local>::launch(tokio::time::sleep);
     /// tokio::spawn(sched_service);
     /// ```
@@ -104,42 +351,97 @@ where
     where
         F: Future<Output = X> + Send + Sync,
         T: Fn(Duration) -> F + Send + Sync + 'static,
+    {
+        Self::launch_with_clock(SystemClock, timer)
+    }
+
+    /// Initializes the scheduler and its connected service with a custom clock.
+    ///
+    /// The clock is used by the service to get the current time, whereas the timer
+    /// function is used to sleep until the next job is due. See [`VirtualClock`]
+    /// for a clock that is controlled by the caller.
+    ///
+    /// ```
+    /// # use async_cron_scheduler::{Scheduler, SystemClock};
+    /// # use chrono::offset::Local;
+    /// let (mut scheduler, sched_service) =
+    ///     Scheduler::<Local>::launch_with_clock(SystemClock, smol::Timer::after);
+    /// ```
+    ///
+    /// [`VirtualClock`]: crate::VirtualClock
+    pub fn launch_with_clock<C, F, T, X>(
+        clock: C,
+        timer: T,
+    ) -> (Self, impl Future<Output = ()> + Send + Sync + 'static)
+    where
+        C: Clock<Tz>,
+        F: Future<Output = X> + Send + Sync,
+        T: Fn(Duration) -> F + Send + Sync + 'static,
     {
         let (sender, receiver) = flume::unbounded();
 
         let task = async move {
             let mut state = SchedulerModel {
+                clock,
                 tasks: SecondaryMap::new(),
                 next: None,
+                running: FuturesUnordered::new(),
             };
 
             loop {
-                match state.next.take() {
-                    Some((key, duration)) => {
-                        let message = receiver.recv_async();
-                        let wait = timer(duration);
+                let wake = {
+                    let sleep = state
+                        .next
+                        .as_ref()
+                        .map(|(_, date_time)| time_until(date_time, state.clock.now()));
+
+                    let message = receiver.recv_async();
 
-                        futures::pin_mut!(message);
-                        futures::pin_mut!(wait);
+                    let wait = async {
+                        match sleep {
+                            Some(duration) => {
+                                timer(duration).await;
+                            }
+                            None => futures::future::pending().await,
+                        }
+                    };
 
-                        match futures::future::select(message, wait).await {
-                            Either::Left((message, _)) => match message {
-                                Ok(message) => state.update(message),
-                                Err(RecvError::Disconnected) => break,
-                            },
+                    let running = &mut state.running;
+                    let completed = async move {
+                        match running.next().await {
+                            Some(key) => key,
+                            None => futures::future::pending().await,
+                        }
+                    };
 
-                            Either::Right((_, _)) => {
+                    futures::pin_mut!(message);
+                    futures::pin_mut!(wait);
+                    futures::pin_mut!(completed);
+
+                    match futures::future::select(message, futures::future::select(wait, completed))
+                        .await
+                    {
+                        Either::Left((message, _)) => Wake::Message(message),
+                        Either::Right((Either::Left(_), _)) => Wake::Timer,
+                        Either::Right((Either::Right((key, _)), _)) => Wake::Completed(key),
+                    }
+                };
+
+                match wake {
+                    Wake::Message(Ok(message)) => state.update(message),
+                    Wake::Message(Err(RecvError::Disconnected)) => break,
+
+                    Wake::Timer => {
+                        if let Some((key, date_time)) = state.next.clone() {
+                            if date_time <= state.clock.now() {
                                 state.call(key);
                                 state.next();
                             }
                         }
                     }
 
-                    None => match receiver.recv_async().await {
-                        Ok(message) => state.update(message),
-                        Err(RecvError::Disconnected) => break,
-                    },
-                };
+                    Wake::Completed(key) => state.completed(key),
+                }
             }
         };
 
@@ -152,31 +454,141 @@ where
         )
     }
 }
-struct SchedulerModel<Tz: TimeZoneExt> {
-    tasks: SecondaryMap<DefaultKey, (Job<Tz>, Command)>,
-    next: Option<(DefaultKey, Duration)>,
+
+/// The command of a job, and the state of its runs.
+enum Task {
+    Sync(Command),
+    Async(AsyncCommand),
+}
+
+/// A job being managed by the scheduler service.
+struct Scheduled<Tz: TimeZoneExt> {
+    job: Box<Job<Tz>>,
+    task: Task,
+    running: usize,
+    queued: bool,
+    paused: bool,
+    postponed: bool,
+}
+
+/// What woke the scheduler service.
+enum Wake<Tz: TimeZoneExt> {
+    Message(Result<SchedMessage<Tz>, RecvError>),
+    Timer,
+    Completed(DefaultKey),
 }
 
-impl<Tz: TimeZoneExt> SchedulerModel<Tz> {
+struct SchedulerModel<Tz: TimeZoneExt, C: Clock<Tz>> {
+    clock: C,
+    tasks: SecondaryMap<DefaultKey, Scheduled<Tz>>,
+    next: Option<(DefaultKey, DateTime<Tz>)>,
+    running: FuturesUnordered<Pin<Box<dyn Future<Output = DefaultKey> + Send + Sync>>>,
+}
+
+impl<Tz: TimeZoneExt, C: Clock<Tz>> SchedulerModel<Tz, C> {
     pub fn call(&mut self, key: DefaultKey) {
-        if let Some((job, func)) = self.tasks.get_mut(key) {
-            func(JobId(key));
+        let now = self.clock.now();
+
+        let postponed = match self.tasks.get_mut(key) {
+            Some(scheduled) => std::mem::take(&mut scheduled.postponed),
+            None => return,
+        };
+
+        match self.tasks.get(key) {
+            Some(scheduled) if !postponed && scheduled.job.expired(&now) => {
+                #[cfg(feature = "logging")]
+                tracing::info!("skipping a job that could not start inside its window");
+            }
+            Some(_) => self.run(key),
+            None => return,
+        }
+
+        let Some(Scheduled { job, .. }) = self.tasks.get_mut(key) else {
+            return;
+        };
 
-            if let Some(next) = job.iterator.next() {
-                job.next = next;
+        // A postponed run may have passed any number of occurrences.
+        if postponed {
+            if !job.start(&now) {
+                self.tasks.remove(key);
+            }
 
+            return;
+        }
+
+        // Occurrences missed while the service was not awake, such as while the
+        // system was suspended, are coalesced into this one.
+        while job.advance() {
+            if job.next > now {
                 return;
             }
+        }
+
+        self.tasks.remove(key);
+    }
+
+    /// Runs the command of a job, unless its overlap policy says otherwise.
+    pub fn run(&mut self, key: DefaultKey) {
+        let Some(scheduled) = self.tasks.get_mut(key) else {
+            return;
+        };
+
+        match scheduled.task {
+            Task::Sync(ref command) => command(JobId(key)),
+
+            Task::Async(ref command) => {
+                if scheduled.running != 0 {
+                    match scheduled.job.overlap {
+                        Overlap::Parallel => (),
+                        Overlap::Queue => {
+                            #[cfg(feature = "logging")]
+                            tracing::info!("queueing a job that is still running");
+
+                            scheduled.queued = true;
+                            return;
+                        }
+                        Overlap::Skip => {
+                            #[cfg(feature = "logging")]
+                            tracing::info!("skipping a job that is still running");
+
+                            return;
+                        }
+                    }
+                }
+
+                let run = command(JobId(key));
+                scheduled.running += 1;
+                self.running.push(Box::pin(async move {
+                    run.await;
+                    key
+                }));
+            }
+        }
+    }
+
+    /// Marks a run of a job as completed, starting the next run if one was queued.
+    pub fn completed(&mut self, key: DefaultKey) {
+        let Some(scheduled) = self.tasks.get_mut(key) else {
+            return;
+        };
+
+        scheduled.running -= 1;
 
-            self.tasks.remove(key);
+        if scheduled.running == 0 && scheduled.queued {
+            scheduled.queued = false;
+            self.run(key);
         }
     }
 
     pub fn next(&mut self) {
+        self.next = None;
+
         loop {
             let mut next: Option<(DefaultKey, DateTime<Tz>)> = None;
 
-            for (id, (job, _)) in self.tasks.iter() {
+            let scheduled = self.tasks.iter().filter(|(_, scheduled)| !scheduled.paused);
+
+            for (id, Scheduled { job, .. }) in scheduled {
                 if let Some((_, date_time)) = next.as_ref() {
                     if date_time.timestamp() > job.next.timestamp() {
                         next = Some((id, job.next.clone()));
@@ -189,16 +601,16 @@ impl<Tz: TimeZoneExt> SchedulerModel<Tz> {
             }
 
             if let Some((id, date)) = next {
-                let seconds_until = date.signed_duration_since(Tz::now()).num_seconds();
-                if let Ok(seconds_until) = u64::try_from(seconds_until) {
-                    if seconds_until > 0 {
-                        #[cfg(feature = "logging")]
-                        tracing::info!("next job in {} seconds", seconds_until);
-
-                        let duration = Duration::from_secs(seconds_until as u64);
-                        self.next = Some((id, duration));
-                        return;
-                    }
+                let now = self.clock.now();
+                if date > now {
+                    #[cfg(feature = "logging")]
+                    tracing::info!(
+                        "next job in {} seconds",
+                        date.clone().signed_duration_since(now).num_seconds()
+                    );
+
+                    self.next = Some((id, date));
+                    return;
                 }
 
                 self.call(id);
@@ -211,8 +623,35 @@ impl<Tz: TimeZoneExt> SchedulerModel<Tz> {
 
     pub fn update(&mut self, message: SchedMessage<Tz>) {
         match message {
-            SchedMessage::Insert(id, job, func) => {
-                self.tasks.insert(id.0, (job, func));
+            SchedMessage::Insert(id, job, task) => {
+                let now = self.clock.now();
+                let missed_run = job.missed_run(&now);
+
+                self.tasks.insert(
+                    id.0,
+                    Scheduled {
+                        job,
+                        task,
+                        running: 0,
+                        queued: false,
+                        paused: false,
+                        postponed: false,
+                    },
+                );
+
+                if missed_run {
+                    #[cfg(feature = "logging")]
+                    tracing::info!("catching up on a missed run");
+
+                    self.run(id.0);
+                }
+
+                if let Some(scheduled) = self.tasks.get_mut(id.0) {
+                    if !scheduled.job.start(&now) {
+                        self.tasks.remove(id.0);
+                    }
+                }
+
                 self.next();
             }
 
@@ -220,6 +659,92 @@ impl<Tz: TimeZoneExt> SchedulerModel<Tz> {
                 self.tasks.remove(id.0);
                 self.next();
             }
+
+            SchedMessage::Resync => self.next(),
+
+            SchedMessage::Pause(id, until) => {
+                if let Some(scheduled) = self.tasks.get_mut(id.0) {
+                    match until {
+                        Some(until) => {
+                            scheduled.paused = false;
+                            if !scheduled.job.skip_until(&until) {
+                                self.tasks.remove(id.0);
+                            }
+                        }
+                        None => scheduled.paused = true,
+                    }
+                }
+
+                self.next();
+            }
+
+            SchedMessage::Resume(id) => {
+                let now = self.clock.now();
+                if let Some(scheduled) = self.tasks.get_mut(id.0) {
+                    scheduled.paused = false;
+                    scheduled.postponed = false;
+                    if !scheduled.job.start(&now) {
+                        self.tasks.remove(id.0);
+                    }
+                }
+
+                self.next();
+            }
+
+            SchedMessage::SkipNext(id) => {
+                if let Some(scheduled) = self.tasks.get_mut(id.0) {
+                    scheduled.postponed = false;
+                    if !scheduled.job.advance() {
+                        self.tasks.remove(id.0);
+                    }
+                }
+
+                self.next();
+            }
+
+            SchedMessage::Postpone(id, until) => {
+                if let Some(scheduled) = self.tasks.get_mut(id.0) {
+                    if until > scheduled.job.next {
+                        scheduled.job.next = until;
+                        scheduled.postponed = true;
+                    }
+                }
+
+                self.next();
+            }
+
+            SchedMessage::Trigger(id) => self.run(id.0),
+
+            SchedMessage::Jobs(reply) => {
+                let _res = reply.send(self.tasks.keys().map(JobId).collect());
+            }
+
+            SchedMessage::NextOccurrence(id, reply) => {
+                let _res = reply.send(
+                    self.tasks
+                        .get(id.0)
+                        .filter(|scheduled| !scheduled.paused)
+                        .map(|scheduled| scheduled.job.next.clone()),
+                );
+            }
+
+            SchedMessage::Remaining(id, limit, reply) => {
+                let _res = reply.send(
+                    self.tasks
+                        .get(id.0)
+                        .map(|scheduled| scheduled.job.remaining(limit)),
+                );
+            }
         }
     }
 }
+
+/// How long to sleep before the time should be checked again.
+fn time_until<Tz: TimeZoneExt>(date_time: &DateTime<Tz>, now: DateTime<Tz>) -> Duration {
+    date_time
+        .clone()
+        .signed_duration_since(now)
+        .to_std()
+        .unwrap_or_default()
+        .min(REVALIDATE_INTERVAL)
+}
@@ -0,0 +1,108 @@
+// Copyright 2021-2022 System76 <info@system76.com>
+// SPDX-License-Identifier: MPL-2.0
+
+//! Jobs after the system resumes from suspend, or the wall clock is changed.
+
+use async_cron_scheduler::{Harness, Job, MemoryStore};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+
+fn start() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2022, 1, 1, 0, 30, 0).unwrap()
+}
+
+#[test]
+fn missed_intervals_are_coalesced_on_resync() {
+    let mut harness = Harness::new(start());
+    let check = harness.insert(Job::interval(std::time::Duration::from_secs(12 * 60 * 60)));
+
+    harness.set(start() + Duration::days(3));
+    harness.scheduler.resync();
+
+    assert_eq!(harness.fired(), vec![check]);
+
+    // The next run is an interval after the occurrence that was run.
+    let next = harness.query(|scheduler| scheduler.next_occurrence(check));
+    assert!(next.unwrap() > start() + Duration::days(3));
+}
+
+#[test]
+fn missed_crons_are_coalesced_on_resync() {
+    let mut harness = Harness::new(start());
+    let hourly = harness.insert(Job::cron("0 0 * * * *").unwrap());
+
+    harness.set(start() + Duration::days(3));
+    harness.scheduler.resync();
+
+    assert_eq!(harness.fired(), vec![hourly]);
+
+    let next = harness.query(|scheduler| scheduler.next_occurrence(hourly));
+    assert_eq!(
+        next,
+        Some(Utc.with_ymd_and_hms(2022, 1, 4, 1, 0, 0).unwrap())
+    );
+
+    harness.advance(Duration::hours(3));
+    assert_eq!(harness.fired(), vec![hourly; 3]);
+}
+
+#[test]
+fn catch_up_jobs_run_once_on_resync() {
+    let mut harness = Harness::new(start());
+    let job = Job::cron("0 0 22 * * *")
+        .unwrap()
+        .catch_up(MemoryStore::new(start()));
+    let update = harness.insert(job);
+
+    harness.set(start() + Duration::days(3));
+    harness.scheduler.resync();
+
+    assert_eq!(harness.fired(), vec![update]);
+}
+
+#[test]
+fn wall_clock_is_revalidated_without_resync() {
+    let mut harness = Harness::new(start());
+    let hourly = harness.insert(Job::cron("0 0 * * * *").unwrap());
+
+    // The monotonic timer has not expired, but the service checks the time every minute.
+    harness.set(start() + Duration::days(1));
+    assert!(harness.fired().is_empty());
+
+    harness.advance(Duration::minutes(1));
+    assert_eq!(harness.fired(), vec![hourly]);
+}
+
+#[test]
+fn resumed_jobs_skip_occurrences_missed_while_paused() {
+    let mut harness = Harness::new(start());
+    let hourly = harness.insert(Job::cron("0 0 * * * *").unwrap());
+
+    harness.scheduler.pause(hourly);
+    harness.advance(Duration::hours(5));
+    assert!(harness.fired().is_empty());
+
+    harness.scheduler.resume(hourly);
+    assert!(harness.fired().is_empty());
+
+    let next = harness.query(|scheduler| scheduler.next_occurrence(hourly));
+    assert_eq!(
+        next,
+        Some(Utc.with_ymd_and_hms(2022, 1, 1, 6, 0, 0).unwrap())
+    );
+}
+
+#[test]
+fn expired_windows_are_skipped_on_resync() {
+    let mut harness = Harness::new(start());
+    let job = Job::cron("0 0 22 * * *")
+        .unwrap()
+        .window(std::time::Duration::from_secs(60 * 60));
+    let update = harness.insert(job);
+
+    harness.set(start() + Duration::days(3));
+    harness.scheduler.resync();
+    assert!(harness.fired().is_empty());
+
+    let next = harness.query(|scheduler| scheduler.next_occurrence(update));
+    assert!(next.unwrap() > start() + Duration::days(3));
+}